@@ -26,10 +26,7 @@ async fn main() -> Result<()> {
         })()
         "#;
 
-    let tenent = TenentRouter::new(
-        "localhost",
-        SwappableAppRouter::try_new(code, config.routes)?,
-    );
+    let tenent = TenentRouter::new("localhost", SwappableAppRouter::try_new(code, config)?);
 
     start_server(9090, vec![tenent]).await?;
     Ok(())
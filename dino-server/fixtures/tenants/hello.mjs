@@ -0,0 +1,12 @@
+(function() {
+    async function hello(req) {
+        return {
+            status: 200,
+            headers: {
+                "content-type": "application/json",
+            },
+            body: JSON.stringify(req),
+        };
+    }
+    return { hello: hello };
+})()
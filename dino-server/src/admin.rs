@@ -0,0 +1,615 @@
+use crate::{
+    router::calc_version, tenant::tenant_key, AppError, AppState, Canary, JsWorker, ProjectConfig,
+    SwappableAppRouter, Tenant,
+};
+use axum::{
+    extract::{DefaultBodyLimit, Path, Request, State},
+    http::header::AUTHORIZATION,
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post, put},
+    Json, Router,
+};
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// bundles uploaded by `dino deploy` are larger than the default limit of axum
+pub const DEFAULT_ADMIN_MAX_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+struct AdminState {
+    state: AppState,
+    // digests are compared in constant time
+    token: blake3::Hash,
+}
+
+/// a built project uploaded to the admin api
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Deployment {
+    /// defaults to the hash of the code
+    pub version: Option<String>,
+    /// content of the built `.mjs` file
+    pub code: String,
+    /// content of the `.yml` config
+    pub config: String,
+}
+
+/// a built project uploaded to the admin api as the canary of a tenant
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CanaryDeployment {
+    /// percentage of the traffic served by the canary
+    pub weight: u8,
+    pub sticky_header: Option<String>,
+    #[serde(flatten)]
+    pub deployment: Deployment,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TenantInfo {
+    pub host: String,
+    pub active: String,
+    /// deployed versions, the oldest first
+    pub versions: Vec<String>,
+    pub canary: Option<CanaryInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CanaryInfo {
+    pub active: String,
+    pub weight: u8,
+    pub sticky_header: Option<String>,
+}
+
+/// the admin api shares the tenants with the app server through `state`
+///
+/// a tenant mounted at a path is addressed as `{host}%2F{mount}`, e.g.
+/// `/tenants/localhost%2Fsvc%2Fhello`, so the host stays a single path segment
+pub fn admin_router(state: AppState, token: impl Into<String>, max_body_size: usize) -> Router {
+    let state = AdminState {
+        state,
+        token: blake3::hash(token.into().as_bytes()),
+    };
+
+    Router::new()
+        .route("/tenants", get(list_tenants))
+        .route(
+            "/tenants/{host}",
+            get(get_tenant).put(deploy).delete(remove_tenant),
+        )
+        .route("/tenants/{host}/rollback", post(rollback))
+        .route(
+            "/tenants/{host}/canary",
+            put(deploy_canary).delete(remove_canary),
+        )
+        .route("/tenants/{host}/canary/promote", post(promote_canary))
+        .route(
+            "/tenants/{host}/versions/{version}/activate",
+            post(activate),
+        )
+        .layer(middleware::from_fn_with_state(state.clone(), verify_token))
+        .layer(DefaultBodyLimit::max(max_body_size))
+        .with_state(state)
+}
+
+/// serve the admin api until `shutdown` is cancelled
+pub async fn start_admin_server(
+    addr: SocketAddr,
+    token: impl Into<String>,
+    max_body_size: usize,
+    state: AppState,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Admin listening on: {}", addr);
+
+    axum::serve(listener, admin_router(state, token, max_body_size))
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
+
+async fn verify_token(
+    State(state): State<AdminState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match token {
+        Some(token) if blake3::hash(token.as_bytes()) == state.token => Ok(next.run(req).await),
+        _ => Err(AppError::Unauthorized),
+    }
+}
+
+async fn list_tenants(State(state): State<AdminState>) -> Json<Vec<TenantInfo>> {
+    let mut tenants = state
+        .state
+        .routes
+        .iter()
+        .map(|r| TenantInfo::new(r.key(), r.value()))
+        .collect::<Vec<_>>();
+    tenants.sort_by(|a, b| a.host.cmp(&b.host));
+
+    Json(tenants)
+}
+
+async fn get_tenant(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+) -> Result<Json<TenantInfo>, AppError> {
+    let host = admin_key(&host);
+    let tenant = find_tenant(&state, &host)?;
+    Ok(Json(TenantInfo::new(&host, &tenant)))
+}
+
+/// deploy a new version for the host, the tenant is created if it doesn't exist
+async fn deploy(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+    Json(deployment): Json<Deployment>,
+) -> Result<Json<TenantInfo>, AppError> {
+    let host = admin_key(&host);
+    let (version, code, config) = prepare(deployment)?;
+
+    let tenant = match state.state.routes.entry(host.clone()) {
+        Entry::Occupied(entry) => {
+            entry.get().primary.deploy(&version, code, config)?;
+            entry.get().clone()
+        }
+        Entry::Vacant(entry) => {
+            let router = SwappableAppRouter::try_new_with_version(&version, code, config)?
+                .with_max_versions(state.state.max_versions);
+            entry.insert(Tenant::new(router)).clone()
+        }
+    };
+    info!("Deployed version {} for {}", version, host);
+
+    Ok(Json(TenantInfo::new(&host, &tenant)))
+}
+
+/// deploy a new version as the canary of the host, the weight is updated as well
+async fn deploy_canary(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+    Json(canary): Json<CanaryDeployment>,
+) -> Result<Json<TenantInfo>, AppError> {
+    let host = admin_key(&host);
+    if canary.weight > 100 {
+        return Err(AppError::InvalidBundle(format!(
+            "canary weight {} must be in 0..=100",
+            canary.weight
+        )));
+    }
+
+    let tenant = find_tenant(&state, &host)?;
+    let (version, code, config) = prepare(canary.deployment)?;
+
+    let router = match tenant.canary() {
+        Some(c) => {
+            c.router.deploy(&version, code, config)?;
+            c.router.clone()
+        }
+        None => SwappableAppRouter::try_new_with_version(&version, code, config)?
+            .with_max_versions(state.state.max_versions),
+    };
+    tenant.set_canary(Some(Canary {
+        router,
+        weight: canary.weight,
+        sticky_header: canary.sticky_header,
+    }));
+    info!(
+        "Deployed canary version {} for {} with weight {}",
+        version, host, canary.weight
+    );
+
+    Ok(Json(TenantInfo::new(&host, &tenant)))
+}
+
+async fn promote_canary(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+) -> Result<Json<TenantInfo>, AppError> {
+    let host = admin_key(&host);
+    let tenant = find_tenant(&state, &host)?;
+    tenant
+        .promote_canary()
+        .map_err(|_| AppError::VersionNotFound(format!("canary of {host}")))?;
+    info!("Promoted canary for {}", host);
+
+    Ok(Json(TenantInfo::new(&host, &tenant)))
+}
+
+async fn remove_canary(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+) -> Result<Json<TenantInfo>, AppError> {
+    let host = admin_key(&host);
+    let tenant = find_tenant(&state, &host)?;
+    tenant.set_canary(None);
+    info!("Removed canary for {}", host);
+
+    Ok(Json(TenantInfo::new(&host, &tenant)))
+}
+
+async fn activate(
+    State(state): State<AdminState>,
+    Path((host, version)): Path<(String, String)>,
+) -> Result<Json<TenantInfo>, AppError> {
+    let host = admin_key(&host);
+    let tenant = find_tenant(&state, &host)?;
+    tenant.primary.activate(&version)?;
+    info!("Activated version {} for {}", version, host);
+
+    Ok(Json(TenantInfo::new(&host, &tenant)))
+}
+
+async fn rollback(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+) -> Result<Json<TenantInfo>, AppError> {
+    let host = admin_key(&host);
+    let tenant = find_tenant(&state, &host)?;
+    let version = tenant.primary.rollback()?;
+    info!("Rolled back to version {} for {}", version, host);
+
+    Ok(Json(TenantInfo::new(&host, &tenant)))
+}
+
+async fn remove_tenant(
+    State(state): State<AdminState>,
+    Path(host): Path<String>,
+) -> Result<Json<TenantInfo>, AppError> {
+    let host = admin_key(&host);
+    let (host, tenant) = state
+        .state
+        .routes
+        .remove(&host)
+        .ok_or(AppError::HostNotFound(host))?;
+    // aliases share the tenant, they would keep serving it
+    state.state.routes.retain(|_, t| !t.is_same(&tenant));
+    info!("Removed tenant {}", host);
+
+    Ok(Json(TenantInfo::new(&host, &tenant)))
+}
+
+/// hosts are matched case-insensitively, a mounted tenant is addressed as `{host}/{mount}`
+fn admin_key(host: &str) -> String {
+    match host.split_once('/') {
+        Some((host, mount)) => tenant_key(host, Some(mount)),
+        None => tenant_key(host, None),
+    }
+}
+
+fn find_tenant(state: &AdminState, host: &str) -> Result<Tenant, AppError> {
+    state
+        .state
+        .routes
+        .get(host)
+        .map(|r| r.clone())
+        .ok_or_else(|| AppError::HostNotFound(host.to_string()))
+}
+
+/// parse the config and make sure the bundle can be evaluated before it takes traffic
+fn prepare(deployment: Deployment) -> Result<(String, String, ProjectConfig), AppError> {
+    let config: ProjectConfig = serde_yml::from_str(&deployment.config)?;
+    // the files are not part of the deployment, a relative dir would be one of the server
+    if !config.statics.is_empty() {
+        return Err(AppError::InvalidConfig(
+            "static files are not part of a deployment, remove `static` from the config"
+                .to_string(),
+        ));
+    }
+
+    JsWorker::try_new(&deployment.code).map_err(|e| AppError::InvalidBundle(e.to_string()))?;
+
+    let version = deployment
+        .version
+        .unwrap_or_else(|| calc_version(&deployment.code));
+
+    Ok((version, deployment.code, config))
+}
+
+impl TenantInfo {
+    pub(crate) fn new(host: &str, tenant: &Tenant) -> Self {
+        let canary = tenant.canary().map(|c| CanaryInfo {
+            active: c.router.active_version(),
+            weight: c.weight,
+            sticky_header: c.sticky_header.clone(),
+        });
+
+        Self {
+            host: host.to_string(),
+            active: tenant.primary.active_version(),
+            versions: tenant.primary.versions(),
+            canary,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Method, StatusCode},
+    };
+    use dashmap::DashMap;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "secret";
+
+    fn deployment(version: &str) -> Deployment {
+        Deployment {
+            version: Some(version.to_string()),
+            code: include_str!("../fixtures/tenants/hello.mjs").to_string(),
+            config: include_str!("../fixtures/config.yml").to_string(),
+        }
+    }
+
+    async fn send(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Option<TenantInfo>) {
+        let body = match body {
+            Some(body) => Body::from(serde_json::to_vec(&body).unwrap()),
+            None => Body::empty(),
+        };
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, format!("Bearer {TOKEN}"))
+            .header("content-type", "application/json")
+            .body(body)
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    #[tokio::test]
+    async fn admin_should_reject_invalid_token() {
+        let app = admin_router(
+            AppState::new(DashMap::new()),
+            TOKEN,
+            DEFAULT_ADMIN_MAX_BODY_SIZE,
+        );
+        let req = Request::builder()
+            .uri("/tenants")
+            .header(AUTHORIZATION, "Bearer wrong")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn admin_deploy_and_rollback_should_work() {
+        let state = AppState::new(DashMap::new());
+        let app = admin_router(state.clone(), TOKEN, DEFAULT_ADMIN_MAX_BODY_SIZE);
+
+        let (status, info) = send(
+            &app,
+            Method::PUT,
+            "/tenants/localhost",
+            Some(json!(deployment("v1"))),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(info.unwrap().active, "v1");
+        assert!(state.routes.contains_key("localhost"));
+
+        let (_, info) = send(
+            &app,
+            Method::PUT,
+            "/tenants/localhost",
+            Some(json!(deployment("v2"))),
+        )
+        .await;
+        let info = info.unwrap();
+        assert_eq!(info.active, "v2");
+        assert_eq!(info.versions, ["v1", "v2"]);
+
+        let (_, info) = send(&app, Method::POST, "/tenants/localhost/rollback", None).await;
+        assert_eq!(info.unwrap().active, "v1");
+
+        let (_, info) = send(
+            &app,
+            Method::POST,
+            "/tenants/localhost/versions/v2/activate",
+            None,
+        )
+        .await;
+        assert_eq!(info.unwrap().active, "v2");
+
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            "/tenants/localhost/versions/v3/activate",
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&app, Method::DELETE, "/tenants/localhost", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!state.routes.contains_key("localhost"));
+    }
+
+    #[tokio::test]
+    async fn admin_should_normalize_hosts() {
+        let router = SwappableAppRouter::try_new(
+            include_str!("../fixtures/tenants/hello.mjs"),
+            serde_yml::from_str(include_str!("../fixtures/config.yml")).unwrap(),
+        )
+        .unwrap();
+        let state =
+            AppState::from(vec![crate::TenentRouter::new("api.example.com", router)
+                .with_aliases(["api.example.org"])]);
+        let app = admin_router(state.clone(), TOKEN, DEFAULT_ADMIN_MAX_BODY_SIZE);
+
+        let (status, _) = send(
+            &app,
+            Method::PUT,
+            "/tenants/API.Example.com.",
+            Some(json!(deployment("v1"))),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, info) = send(&app, Method::GET, "/tenants/api.example.org", None).await;
+        assert_eq!(info.unwrap().active, "v1");
+
+        // the aliases are removed with the tenant
+        let (status, _) = send(&app, Method::DELETE, "/tenants/API.example.com", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.routes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn admin_should_address_mounted_tenants() {
+        let router = SwappableAppRouter::try_new(
+            include_str!("../fixtures/tenants/hello.mjs"),
+            serde_yml::from_str(include_str!("../fixtures/config.yml")).unwrap(),
+        )
+        .unwrap();
+        let state = AppState::from(vec![
+            crate::TenentRouter::new("localhost", router).with_mount("/svc/hello")
+        ]);
+        let app = admin_router(state, TOKEN, DEFAULT_ADMIN_MAX_BODY_SIZE);
+
+        let (status, info) =
+            send(&app, Method::GET, "/tenants/localhost%2Fsvc%2Fhello", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(info.unwrap().host, "localhost/svc/hello");
+        let (status, _) = send(&app, Method::GET, "/tenants/localhost/svc/hello", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn admin_should_limit_body_size() {
+        let mut deployment = deployment("v1");
+        // larger than the default limit of axum
+        deployment
+            .code
+            .push_str(&format!("\n//{}", "x".repeat(3 * 1024 * 1024)));
+        let body = Some(json!(deployment));
+
+        let app = admin_router(
+            AppState::new(DashMap::new()),
+            TOKEN,
+            DEFAULT_ADMIN_MAX_BODY_SIZE,
+        );
+        let (status, _) = send(&app, Method::PUT, "/tenants/localhost", body.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let app = admin_router(AppState::new(DashMap::new()), TOKEN, 1024);
+        let (status, _) = send(&app, Method::PUT, "/tenants/localhost", body).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn admin_should_reject_invalid_bundle() {
+        let app = admin_router(
+            AppState::new(DashMap::new()),
+            TOKEN,
+            DEFAULT_ADMIN_MAX_BODY_SIZE,
+        );
+        let mut deployment = deployment("v1");
+        deployment.code = "(function() {".to_string();
+
+        let (status, _) = send(
+            &app,
+            Method::PUT,
+            "/tenants/localhost",
+            Some(json!(deployment)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn admin_should_reject_static_files() {
+        let app = admin_router(
+            AppState::new(DashMap::new()),
+            TOKEN,
+            DEFAULT_ADMIN_MAX_BODY_SIZE,
+        );
+        let mut deployment = deployment("v1");
+        deployment.config = include_str!("../fixtures/tenants/hello.yml").to_string();
+
+        let (status, _) = send(
+            &app,
+            Method::PUT,
+            "/tenants/localhost",
+            Some(json!(deployment)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn admin_canary_should_work() {
+        let state = AppState::new(DashMap::new());
+        let app = admin_router(state.clone(), TOKEN, DEFAULT_ADMIN_MAX_BODY_SIZE);
+
+        let canary = json!(CanaryDeployment {
+            weight: 5,
+            sticky_header: None,
+            deployment: deployment("v2"),
+        });
+        let (status, _) = send(
+            &app,
+            Method::PUT,
+            "/tenants/localhost/canary",
+            Some(canary.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        send(
+            &app,
+            Method::PUT,
+            "/tenants/localhost",
+            Some(json!(deployment("v1"))),
+        )
+        .await;
+        let (_, info) = send(&app, Method::PUT, "/tenants/localhost/canary", Some(canary)).await;
+        let canary = info.unwrap().canary.unwrap();
+        assert_eq!(canary.active, "v2");
+        assert_eq!(canary.weight, 5);
+
+        let (_, info) = send(
+            &app,
+            Method::POST,
+            "/tenants/localhost/canary/promote",
+            None,
+        )
+        .await;
+        let info = info.unwrap();
+        assert_eq!(info.active, "v2");
+        assert!(info.canary.is_none());
+
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            "/tenants/localhost/canary/promote",
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{conditional::etag_matches, ProjectConfig, StaticConfig};
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{CACHE_CONTROL, ETAG},
+        request::Parts,
+        HeaderValue, Method, StatusCode,
+    },
+    response::Response,
+};
+use std::{
+    fs::Metadata,
+    path::{Component, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+/// served for paths ending with `/`
+const INDEX_FILE: &str = "index.html";
+
+/// the file of the first static prefix matching the path, `None` to fall back to the routes
+///
+/// mime types, `last-modified` and range requests are handled by `ServeFile`
+pub(crate) async fn serve_static(
+    config: &ProjectConfig,
+    path: &str,
+    parts: &Parts,
+) -> Option<(String, Response)> {
+    if parts.method != Method::GET && parts.method != Method::HEAD {
+        return None;
+    }
+
+    for (prefix, statics) in &config.statics {
+        let Some(file) = static_file(prefix, statics, path) else {
+            continue;
+        };
+        let Ok(metadata) = tokio::fs::metadata(&file).await else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let etag = etag(&metadata);
+        let mut res = if etag_matches(&parts.headers, &etag) {
+            let mut res = Response::new(Body::empty());
+            *res.status_mut() = StatusCode::NOT_MODIFIED;
+            res
+        } else {
+            let mut req = Request::new(Body::empty());
+            *req.method_mut() = parts.method.clone();
+            *req.uri_mut() = parts.uri.clone();
+            *req.headers_mut() = parts.headers.clone();
+            let Ok(res) = ServeFile::new(&file).oneshot(req).await;
+            res.map(Body::new)
+        };
+
+        let cache_control = match statics.max_age {
+            Some(max_age) => format!("public, max-age={}", max_age.as_secs()),
+            None => "no-cache".to_string(),
+        };
+        let headers = res.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&etag) {
+            headers.insert(ETAG, etag);
+        }
+        if let Ok(cache_control) = HeaderValue::from_str(&cache_control) {
+            headers.insert(CACHE_CONTROL, cache_control);
+        }
+
+        return Some((prefix.clone(), res));
+    }
+
+    None
+}
+
+/// the file under the directory of the prefix, `None` if the path escapes it
+fn static_file(prefix: &str, statics: &StaticConfig, path: &str) -> Option<PathBuf> {
+    let rest = path.strip_prefix(prefix.trim_end_matches('/'))?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+
+    let mut file = statics.dir.clone();
+    for component in Path::new(rest.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(name) => file.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if rest.is_empty() || rest.ends_with('/') {
+        file.push(INDEX_FILE);
+    }
+
+    Some(file)
+}
+
+/// changes whenever the file is modified
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::header::{CONTENT_RANGE, CONTENT_TYPE, IF_NONE_MATCH, RANGE};
+    use http_body_util::BodyExt;
+
+    fn parts(uri: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut req = Request::builder().uri(uri);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(()).unwrap().into_parts().0
+    }
+
+    async fn serve(uri: &str, headers: &[(&str, &str)]) -> Option<Response> {
+        let config = ProjectConfig::load("fixtures/tenants/hello.yml").unwrap();
+        let parts = parts(uri, headers);
+        serve_static(&config, parts.uri.path(), &parts)
+            .await
+            .map(|(_, res)| res)
+    }
+
+    async fn body(res: Response) -> String {
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serve_static_should_work() -> anyhow::Result<()> {
+        let res = serve("/assets/css/app.css", &[]).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/css");
+        assert_eq!(res.headers()[CACHE_CONTROL], "public, max-age=3600");
+        let etag = res.headers()[ETAG].to_str()?.to_string();
+
+        let res = serve("/assets/", &[]).await.unwrap();
+        assert_eq!(body(res).await, "<h1>dino</h1>\n");
+
+        let res = serve("/assets/hello.txt", &[(IF_NONE_MATCH.as_str(), &etag)])
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.headers()[ETAG].to_str()?.to_string();
+        let res = serve("/assets/hello.txt", &[(IF_NONE_MATCH.as_str(), &etag)])
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let res = serve("/assets/hello.txt", &[(RANGE.as_str(), "bytes=6-")])
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes 6-10/11");
+        assert_eq!(body(res).await, "dino\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serve_static_should_fall_back() {
+        assert!(serve("/assets/missing.txt", &[]).await.is_none());
+        assert!(serve("/assets/../../hello.yml", &[]).await.is_none());
+        assert!(serve("/assetsx/hello.txt", &[]).await.is_none());
+        assert!(serve("/api/hello/1", &[]).await.is_none());
+    }
+}
@@ -0,0 +1,438 @@
+use crate::{CacheConfig, Res};
+use axum::http::{header::AUTHORIZATION, HeaderMap};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// status codes cacheable by default, see RFC 9111
+const CACHEABLE_STATUS: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// responses of a version of a project, dropped when another version is installed
+#[derive(Debug)]
+pub struct ResponseCache {
+    max_size: usize,
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    // variants of a request by the headers named in `vary`
+    entries: HashMap<String, Vec<CacheEntry>>,
+    size: usize,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    /// the request headers named in `vary` when the response was stored
+    vary: Vec<(String, Option<String>)>,
+    res: Res,
+    size: usize,
+    stored_at: Instant,
+    fresh_for: Duration,
+    stale_for: Duration,
+    revalidating: bool,
+}
+
+/// the `x-dino-cache` header of a cacheable response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Stale,
+    Miss,
+}
+
+#[derive(Debug)]
+pub struct Cached {
+    pub res: Res,
+    pub age: Duration,
+    pub status: CacheStatus,
+    /// true for the first request served with a stale response
+    pub revalidate: bool,
+}
+
+impl ResponseCache {
+    /// `max_size` is the total size of the cached responses in bytes
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    pub fn get(&self, key: &str, headers: &HeaderMap) -> Option<Cached> {
+        self.get_at(key, headers, Instant::now())
+    }
+
+    /// store the response if its `cache-control` or the config allows it
+    pub fn put(&self, key: &str, headers: &HeaderMap, res: &Res, config: Option<&CacheConfig>) {
+        self.put_at(key, headers, res, config, Instant::now())
+    }
+
+    /// let the next request revalidate the stale response again
+    pub fn release(&self, key: &str, headers: &HeaderMap) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner
+            .entries
+            .get_mut(key)
+            .and_then(|entries| entries.iter_mut().find(|e| e.matches(headers)))
+        {
+            entry.revalidating = false;
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.size = 0;
+    }
+
+    pub fn size(&self) -> usize {
+        self.inner.lock().unwrap().size
+    }
+
+    fn get_at(&self, key: &str, headers: &HeaderMap, now: Instant) -> Option<Cached> {
+        let mut inner = self.inner.lock().unwrap();
+        let entries = inner.entries.get_mut(key)?;
+        let pos = entries.iter().position(|e| e.matches(headers))?;
+
+        let entry = &mut entries[pos];
+        let age = now.saturating_duration_since(entry.stored_at);
+        if age < entry.fresh_for {
+            return Some(Cached {
+                res: entry.res.clone(),
+                age,
+                status: CacheStatus::Hit,
+                revalidate: false,
+            });
+        }
+        if age < entry.fresh_for + entry.stale_for {
+            let revalidate = !entry.revalidating;
+            entry.revalidating = true;
+            return Some(Cached {
+                res: entry.res.clone(),
+                age,
+                status: CacheStatus::Stale,
+                revalidate,
+            });
+        }
+
+        inner.remove(key, pos);
+        None
+    }
+
+    fn put_at(
+        &self,
+        key: &str,
+        headers: &HeaderMap,
+        res: &Res,
+        config: Option<&CacheConfig>,
+        now: Instant,
+    ) {
+        if !CACHEABLE_STATUS.contains(&res.status) || res.header("set-cookie").is_some() {
+            return;
+        }
+
+        let directives = res
+            .header("cache-control")
+            .map(CacheControl::parse)
+            .unwrap_or_default();
+        if directives.no_store || (headers.contains_key(AUTHORIZATION) && !directives.public) {
+            return;
+        }
+        let Some(fresh_for) = directives.max_age.or_else(|| config.and_then(|c| c.ttl)) else {
+            return;
+        };
+        let stale_for = directives
+            .stale_while_revalidate
+            .or_else(|| config.and_then(|c| c.stale_while_revalidate))
+            .unwrap_or_default();
+        if fresh_for.is_zero() && stale_for.is_zero() {
+            return;
+        }
+
+        let mut vary = Vec::new();
+        for name in res.header("vary").unwrap_or_default().split(',') {
+            let name = name.trim().to_ascii_lowercase();
+            match name.as_str() {
+                "" => continue,
+                "*" => return,
+                _ => {
+                    let value = request_header(headers, &name);
+                    vary.push((name, value));
+                }
+            }
+        }
+
+        let size = key.len()
+            + res.body.as_ref().map(|b| b.len()).unwrap_or_default()
+            + res
+                .headers
+                .iter()
+                .map(|(k, v)| k.len() + v.len())
+                .sum::<usize>();
+        if size > self.max_size {
+            return;
+        }
+
+        let entry = CacheEntry {
+            vary,
+            res: res.clone(),
+            size,
+            stored_at: now,
+            fresh_for,
+            stale_for,
+            revalidating: false,
+        };
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(pos) = inner
+            .entries
+            .get(key)
+            .and_then(|entries| entries.iter().position(|e| e.vary == entry.vary))
+        {
+            inner.remove(key, pos);
+        }
+        while inner.size + size > self.max_size {
+            inner.evict_oldest();
+        }
+        inner.size += size;
+        inner
+            .entries
+            .entry(key.to_string())
+            .or_default()
+            .push(entry);
+    }
+}
+
+impl CacheInner {
+    fn remove(&mut self, key: &str, pos: usize) {
+        let Some(entries) = self.entries.get_mut(key) else {
+            return;
+        };
+        let entry = entries.remove(pos);
+        self.size -= entry.size;
+        if entries.is_empty() {
+            self.entries.remove(key);
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .flat_map(|(key, entries)| {
+                entries
+                    .iter()
+                    .enumerate()
+                    .map(move |(pos, e)| (e.stored_at, key, pos))
+            })
+            .min()
+            .map(|(_, key, pos)| (key.clone(), pos));
+        match oldest {
+            Some((key, pos)) => self.remove(&key, pos),
+            None => self.size = 0,
+        }
+    }
+}
+
+impl CacheEntry {
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request_header(headers, name) == *value)
+    }
+}
+
+impl CacheStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Stale => "STALE",
+            CacheStatus::Miss => "MISS",
+        }
+    }
+}
+
+/// the directives of a response relevant to a shared cache
+#[derive(Debug, Default, PartialEq)]
+struct CacheControl {
+    no_store: bool,
+    public: bool,
+    max_age: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+}
+
+impl CacheControl {
+    fn parse(value: &str) -> Self {
+        let mut directives = Self::default();
+        let mut s_maxage = None;
+        for directive in value.split(',') {
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name, Some(arg.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            let secs = arg
+                .and_then(|arg| arg.parse().ok())
+                .map(Duration::from_secs);
+            match name.trim().to_ascii_lowercase().as_str() {
+                // responses which must be revalidated every time are not worth caching
+                "no-store" | "no-cache" | "private" => directives.no_store = true,
+                "public" => directives.public = true,
+                "max-age" => directives.max_age = secs,
+                "s-maxage" => s_maxage = secs,
+                "stale-while-revalidate" => directives.stale_while_revalidate = secs,
+                _ => {}
+            }
+        }
+        directives.max_age = s_maxage.or(directives.max_age);
+
+        directives
+    }
+}
+
+fn request_header(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>();
+    (!values.is_empty()).then(|| values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn res(headers: &[(&str, &str)], body: &str) -> Res {
+        Res {
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            status: 200,
+            body: Some(body.to_string()),
+        }
+    }
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(k, v)| (k.parse().unwrap(), v.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn cache_control_should_parse() {
+        let directives = CacheControl::parse("public, max-age=60, s-maxage=\"120\"");
+        assert!(directives.public);
+        assert_eq!(directives.max_age, Some(Duration::from_secs(120)));
+
+        let directives = CacheControl::parse("max-age=10, stale-while-revalidate=30");
+        assert_eq!(
+            directives.stale_while_revalidate,
+            Some(Duration::from_secs(30))
+        );
+        assert!(CacheControl::parse("Private, max-age=60").no_store);
+    }
+
+    #[test]
+    fn response_cache_should_work() {
+        let cache = ResponseCache::new(1024);
+        let now = Instant::now();
+        let empty = HeaderMap::new();
+        let config = CacheConfig {
+            ttl: Some(Duration::from_secs(10)),
+            stale_while_revalidate: Some(Duration::from_secs(10)),
+        };
+
+        // not cacheable without max-age or a config
+        cache.put_at("a", &empty, &res(&[], "a"), None, now);
+        assert!(cache.get_at("a", &empty, now).is_none());
+        cache.put_at(
+            "a",
+            &empty,
+            &res(&[("Cache-Control", "no-store")], "a"),
+            Some(&config),
+            now,
+        );
+        assert!(cache.get_at("a", &empty, now).is_none());
+
+        cache.put_at("a", &empty, &res(&[], "a"), Some(&config), now);
+        let cached = cache.get_at("a", &empty, now).unwrap();
+        assert_eq!(cached.status, CacheStatus::Hit);
+        assert_eq!(cached.res.body.as_deref(), Some("a"));
+
+        // only the first stale request revalidates
+        let later = now + Duration::from_secs(15);
+        let cached = cache.get_at("a", &empty, later).unwrap();
+        assert_eq!(cached.status, CacheStatus::Stale);
+        assert!(cached.revalidate);
+        assert!(!cache.get_at("a", &empty, later).unwrap().revalidate);
+        cache.release("a", &empty);
+        assert!(cache.get_at("a", &empty, later).unwrap().revalidate);
+
+        assert!(cache
+            .get_at("a", &empty, now + Duration::from_secs(20))
+            .is_none());
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn response_cache_should_vary() {
+        let cache = ResponseCache::new(1024);
+        let now = Instant::now();
+        let en = headers(&[("accept-language", "en")]);
+        let fr = headers(&[("accept-language", "fr")]);
+        let vary = [("cache-control", "max-age=60"), ("vary", "Accept-Language")];
+
+        cache.put_at("a", &en, &res(&vary, "hello"), None, now);
+        cache.put_at("a", &fr, &res(&vary, "bonjour"), None, now);
+        let cached = cache.get_at("a", &fr, now).unwrap();
+        assert_eq!(cached.res.body.as_deref(), Some("bonjour"));
+        let cached = cache.get_at("a", &en, now).unwrap();
+        assert_eq!(cached.res.body.as_deref(), Some("hello"));
+        assert!(cache.get_at("a", &HeaderMap::new(), now).is_none());
+
+        // the other clients of a shared cache must not see authorized responses
+        let auth = headers(&[("authorization", "Bearer dino")]);
+        cache.put_at("b", &auth, &res(&vary[..1], "secret"), None, now);
+        assert!(cache.get_at("b", &auth, now).is_none());
+    }
+
+    #[test]
+    fn response_cache_should_evict_oldest() {
+        let cache = ResponseCache::new(64);
+        let now = Instant::now();
+        let empty = HeaderMap::new();
+        let config = CacheConfig {
+            ttl: Some(Duration::from_secs(60)),
+            stale_while_revalidate: None,
+        };
+
+        let body = "x".repeat(30);
+        cache.put_at("a", &empty, &res(&[], &body), Some(&config), now);
+        cache.put_at(
+            "b",
+            &empty,
+            &res(&[], &body),
+            Some(&config),
+            now + Duration::from_secs(1),
+        );
+        cache.put_at(
+            "c",
+            &empty,
+            &res(&[], &body),
+            Some(&config),
+            now + Duration::from_secs(2),
+        );
+        assert!(cache.get_at("a", &empty, now).is_none());
+        assert!(cache.get_at("b", &empty, now).is_some());
+        assert!(cache.get_at("c", &empty, now).is_some());
+        assert_eq!(cache.size(), 62);
+
+        // larger than the cache
+        cache.put_at("d", &empty, &res(&[], &body.repeat(3)), Some(&config), now);
+        assert!(cache.get_at("d", &empty, now).is_none());
+    }
+}
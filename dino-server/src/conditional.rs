@@ -0,0 +1,112 @@
+use crate::Res;
+use axum::http::{
+    header::{IF_MODIFIED_SINCE, IF_NONE_MATCH},
+    HeaderMap,
+};
+use chrono::{DateTime, FixedOffset};
+
+/// set a strong etag of the body, unless the handler supplied its own
+pub(crate) fn add_etag(res: &mut Res) {
+    if !(200..300).contains(&res.status) || res.header("etag").is_some() {
+        return;
+    }
+
+    let body = res.body.as_deref().unwrap_or_default();
+    let mut hash = blake3::hash(body.as_bytes()).to_string();
+    hash.truncate(32);
+    res.headers
+        .insert("etag".to_string(), format!("\"{hash}\""));
+}
+
+/// true if the client already has the response, `if-modified-since` is ignored with an
+/// `if-none-match`
+pub(crate) fn not_modified(headers: &HeaderMap, res: &Res) -> bool {
+    if !(200..300).contains(&res.status) {
+        return false;
+    }
+    if headers.contains_key(IF_NONE_MATCH) {
+        return res
+            .header("etag")
+            .is_some_and(|etag| etag_matches(headers, etag));
+    }
+
+    let since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(http_date);
+    let modified = res.header("last-modified").and_then(http_date);
+    matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+}
+
+/// weak comparison, as required for `if-none-match`
+pub(crate) fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+
+    let etag = etag.trim_start_matches("W/");
+    value
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn http_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc2822(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn res(headers: &[(&str, &str)]) -> Res {
+        Res {
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            status: 200,
+            body: Some("hello".to_string()),
+        }
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(name.parse().unwrap(), value.parse().unwrap())])
+    }
+
+    #[test]
+    fn add_etag_should_work() {
+        let mut a = res(&[]);
+        add_etag(&mut a);
+        let mut b = res(&[]);
+        add_etag(&mut b);
+        assert_eq!(a.header("etag"), b.header("etag"));
+        assert!(a.header("etag").unwrap().starts_with('"'));
+
+        // the etag of the handler is kept
+        let mut res = res(&[("ETag", "\"v1\"")]);
+        add_etag(&mut res);
+        assert_eq!(res.header("etag"), Some("\"v1\""));
+        assert_eq!(res.headers.len(), 1);
+    }
+
+    #[test]
+    fn not_modified_should_work() {
+        let tagged = res(&[
+            ("etag", "\"v1\""),
+            ("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT"),
+        ]);
+        assert!(not_modified(
+            &headers("if-none-match", "\"v0\", W/\"v1\""),
+            &tagged
+        ));
+        assert!(!not_modified(&headers("if-none-match", "\"v2\""), &tagged));
+
+        let since = "Thu, 22 Oct 2015 07:28:00 GMT";
+        assert!(not_modified(&headers("if-modified-since", since), &tagged));
+        let since = "Tue, 20 Oct 2015 07:28:00 GMT";
+        assert!(!not_modified(&headers("if-modified-since", since), &tagged));
+        assert!(!not_modified(&HeaderMap::new(), &tagged));
+    }
+}
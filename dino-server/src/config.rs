@@ -1,24 +1,464 @@
-use anyhow::Result;
+use crate::{
+    tenant::tenant_key, Canary, CertConfig, CertStore, SwappableAppRouter, TenentRouter,
+    DEFAULT_ADMIN_MAX_BODY_SIZE, DEFAULT_DRAIN_TIMEOUT, DEFAULT_MAX_VERSIONS,
+};
+use anyhow::{bail, Result};
 use axum::http::Method;
 use indexmap::IndexMap;
 use serde::Deserialize;
-use std::path::Path;
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
 
 pub type ProjectRoutes = IndexMap<String, Vec<ProjectRoute>>;
 
-#[allow(unused)]
+/// same as the default body limit of axum
+pub const DEFAULT_MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+pub const DEFAULT_BODY_READ_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_CERT_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+/// same as the default of tower-http
+pub const DEFAULT_COMPRESSION_MIN_SIZE: u16 = 32;
+pub const DEFAULT_MAX_CACHE_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_PROXY_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_REDIRECT_STATUS: u16 = 301;
+
+/// config of a dino-server process hosting many projects
+#[derive(Deserialize, Debug)]
+pub struct ServerConfig {
+    #[serde(default = "default_addr")]
+    pub addr: SocketAddr,
+    /// the admin api is disabled if not configured
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    /// https is disabled if not configured
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// requests are not logged if not configured
+    #[serde(default)]
+    pub access_log: Option<AccessLogConfig>,
+    /// `/metrics` is not served if not configured
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+    /// traces are not exported if not configured, requires the `otel` feature
+    #[serde(default)]
+    pub otel: Option<OtelConfig>,
+    /// paths of the probes, reserved on every host
+    #[serde(default)]
+    pub probes: ProbeConfig,
+    /// deployed versions kept by every tenant for rollback
+    #[serde(default = "default_max_versions")]
+    pub max_versions: usize,
+    /// time for in-flight requests to finish on shutdown, e.g. `30s`
+    #[serde(default = "default_drain_timeout", with = "humantime_serde")]
+    pub drain_timeout: Duration,
+    pub tenants: Vec<TenantConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AdminConfig {
+    pub addr: SocketAddr,
+    /// bearer token required by every admin request
+    pub token: String,
+    /// largest deployment accepted, in bytes
+    #[serde(default = "default_admin_max_body_size")]
+    pub max_body_size: usize,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TlsConfig {
+    pub addr: SocketAddr,
+    /// plaintext requests to `addr` are redirected to https instead of being served
+    #[serde(default = "default_true")]
+    pub redirect_http: bool,
+    /// how often the certificate files are checked for changes, e.g. `1m`
+    #[serde(default = "default_cert_reload_interval", with = "humantime_serde")]
+    pub reload_interval: Duration,
+    /// certificate for clients without SNI, e.g. connecting by ip, defaults to the first
+    /// tenant with tls
+    #[serde(default)]
+    pub default_host: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MetricsConfig {
+    /// listen address of the prometheus endpoint, kept apart from the tenants
+    pub addr: SocketAddr,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProbeConfig {
+    /// answers as long as the process serves requests
+    pub health: String,
+    /// answers once the bundles of all tenants are evaluated
+    pub ready: String,
+    /// tenants, active versions and uptime
+    pub info: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OtelConfig {
+    /// base url of the otlp/http collector, spans are posted to `{endpoint}/v1/traces`
+    pub endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AccessLogConfig {
+    #[serde(default)]
+    pub format: AccessLogFormat,
+    /// file to write to, e.g. `logs/access.log`, stdout if not set
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// rotated files to keep, all of them if not set
+    #[serde(default)]
+    pub max_files: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// one json object per line
+    #[default]
+    Json,
+    /// common log format
+    Clf,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Never,
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TenantConfig {
+    /// exact host, wildcard like `*.example.com`, or `*` for the default tenant
+    pub host: String,
+    /// other hosts served by the tenant
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// path prefix like `/svc/orders`, the tenant serves the whole host if not set
+    #[serde(default)]
+    pub mount: Option<String>,
+    /// the built `.mjs` file, its `.yml` config must be next to it
+    pub bundle: PathBuf,
+    #[serde(default)]
+    pub canary: Option<CanaryConfig>,
+    /// certificate served for the host when tls is enabled
+    #[serde(default)]
+    pub tls: Option<CertConfig>,
+}
+
 #[derive(Deserialize, Debug)]
+pub struct CanaryConfig {
+    pub bundle: PathBuf,
+    /// percentage of the traffic served by the canary
+    pub weight: u8,
+    #[serde(default)]
+    pub sticky_header: Option<String>,
+}
+
+#[allow(unused)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct ProjectConfig {
     pub name: String,
+    /// max body size in bytes for all routes
+    #[serde(default)]
+    pub max_body_size: Option<usize>,
+    /// max time to receive the whole body for all routes, e.g. `10s`
+    #[serde(default, with = "humantime_serde")]
+    pub body_read_timeout: Option<Duration>,
+    /// cross-origin requests are not allowed if not configured
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    /// limit of every route, requests are not limited if not configured
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// responses are compressed by the default rules of dino if not configured
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+    /// compute a strong etag from the body of the responses without one
+    #[serde(default)]
+    pub etag: bool,
+    /// responses of the handlers are cached only by their `cache-control` if not configured
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+    /// bytes of cached responses kept by every version of the project
+    #[serde(default)]
+    pub max_cache_size: Option<usize>,
+    /// paths in the `matchit` syntax redirected before static files and routes are matched
+    #[serde(default)]
+    pub redirects: IndexMap<String, RedirectConfig>,
+    /// paths in the `matchit` syntax served by another path, e.g. `/blog/{*rest}: /posts/{rest}`
+    #[serde(default)]
+    pub rewrites: IndexMap<String, String>,
+    /// url prefixes like `/assets` served from a directory of the project, before the routes
+    #[serde(default, rename = "static")]
+    pub statics: IndexMap<String, StaticConfig>,
     pub routes: ProjectRoutes,
 }
 
 #[allow(unused)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct ProjectRoute {
     #[serde(deserialize_with = "deserialize_method")]
     pub method: Method,
+    /// the js function of the route, empty for proxy routes
+    #[serde(default)]
     pub handler: String,
+    /// forward the requests to an upstream instead of calling a js function
+    #[serde(default, deserialize_with = "deserialize_proxy")]
+    pub proxy: Option<ProxyConfig>,
+    /// overrides `max_body_size` of the project
+    #[serde(default)]
+    pub max_body_size: Option<usize>,
+    /// overrides `body_read_timeout` of the project
+    #[serde(default, with = "humantime_serde")]
+    pub body_read_timeout: Option<Duration>,
+    #[serde(default)]
+    pub multipart: MultipartConfig,
+    /// replaces `cors` of the project
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    /// replaces `rate_limit` of the project
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// overrides `etag` of the project
+    #[serde(default)]
+    pub etag: Option<bool>,
+    /// replaces `cache` of the project
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+    /// the path pattern the route is registered with, filled when the router is built
+    #[serde(skip)]
+    pub path: String,
+}
+
+/// size limits (in bytes) for `multipart/form-data` bodies of a route
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MultipartConfig {
+    /// limit of the whole multipart stream, defaults to the max body size of the route
+    pub max_size: Option<u64>,
+    /// limit of every single field or file
+    pub max_field_size: Option<u64>,
+}
+
+/// cors policy of a project or a route, preflight requests are answered by dino
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// e.g. `https://example.com`, `*` allows any origin
+    pub origins: Vec<String>,
+    /// methods allowed by preflight requests, the requested method of the route if empty
+    pub methods: Vec<String>,
+    /// request headers allowed by preflight requests, `*` allows any header
+    pub headers: Vec<String>,
+    /// allow cookies and the authorization header
+    pub credentials: bool,
+    /// how long browsers may cache the answer of a preflight request, e.g. `1h`
+    #[serde(with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StaticConfig {
+    /// e.g. `public`, copied into the build directory of the bundle by `dino build`
+    pub dir: PathBuf,
+    /// `max-age` of the `cache-control` header, files are revalidated by etag if not set
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+}
+
+/// which responses are compressed, by the encoding the client accepts
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// smaller bodies are sent as is, in bytes
+    pub min_size: u16,
+    /// prefixes like `text/` or `application/json`, any content type if empty
+    pub content_types: Vec<String>,
+}
+
+/// `proxy: http://legacy:8080` or a map with the url, params of the route like `{id}` are
+/// substituted in the url, the path of the request is appended to a url without a path
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProxyConfig {
+    pub url: String,
+    /// max time to receive the response headers from the upstream
+    #[serde(default = "default_proxy_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// set on the forwarded request, an empty value removes the header
+    #[serde(default)]
+    pub headers: IndexMap<String, String>,
+    /// set on the response of the upstream, an empty value removes the header
+    #[serde(default)]
+    pub response_headers: IndexMap<String, String>,
+}
+
+/// params of the path like `{id}` are substituted in `to`, a path or an absolute url
+#[derive(Deserialize, Debug, Clone)]
+pub struct RedirectConfig {
+    pub to: String,
+    /// 301, 302, 307 or 308
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+    /// append the query of the request to `to`
+    #[serde(default = "default_true")]
+    pub preserve_query: bool,
+    /// the path pattern the redirect is registered with, filled when the router is built
+    #[serde(skip)]
+    pub path: String,
+}
+
+/// caching of `GET` responses, the `cache-control` of a response takes precedence
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// how long responses without `max-age` are fresh, e.g. `30s`
+    #[serde(with = "humantime_serde")]
+    pub ttl: Option<Duration>,
+    /// how long a stale response is served while it is refreshed in the background
+    #[serde(with = "humantime_serde")]
+    pub stale_while_revalidate: Option<Duration>,
+}
+
+/// token bucket refilled with `requests` tokens every `per`, one bucket per route and client key
+#[derive(Deserialize, Debug, Clone)]
+pub struct RateLimitConfig {
+    pub requests: u32,
+    /// e.g. `1s`, `1m`
+    #[serde(with = "humantime_serde")]
+    pub per: Duration,
+    /// size of the bucket, defaults to `requests`
+    #[serde(default)]
+    pub burst: Option<u32>,
+    #[serde(default)]
+    pub key: RateLimitKey,
+}
+
+/// what identifies a client: `ip`, `header:{name}` or `js:{function}`
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum RateLimitKey {
+    /// the ip address of the connection, not limited if the server has no `ConnectInfo`
+    #[default]
+    Ip,
+    /// the value of a header like `x-api-key`, requests without it are limited by their ip
+    Header(String),
+    /// the string returned by a js function of the bundle, called with the request
+    Js(String),
+}
+
+impl TryFrom<String> for RateLimitKey {
+    type Error = String;
+
+    fn try_from(key: String) -> std::result::Result<Self, Self::Error> {
+        match key.split_once(':') {
+            None if key == "ip" => Ok(Self::Ip),
+            Some(("header", name)) if !name.is_empty() => Ok(Self::Header(name.to_string())),
+            Some(("js", name)) if !name.is_empty() => Ok(Self::Js(name.to_string())),
+            _ => Err(format!(
+                "invalid rate limit key {key}, expected ip, header:{{name}} or js:{{function}}"
+            )),
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+            content_types: Vec::new(),
+        }
+    }
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            health: "/healthz".to_string(),
+            ready: "/readyz".to_string(),
+            info: "/_dino/info".to_string(),
+        }
+    }
+}
+
+fn default_addr() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 8080))
+}
+
+fn default_max_versions() -> usize {
+    DEFAULT_MAX_VERSIONS
+}
+
+fn default_drain_timeout() -> Duration {
+    DEFAULT_DRAIN_TIMEOUT
+}
+
+fn default_admin_max_body_size() -> usize {
+    DEFAULT_ADMIN_MAX_BODY_SIZE
+}
+
+fn default_service_name() -> String {
+    "dino-server".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_cert_reload_interval() -> Duration {
+    DEFAULT_CERT_RELOAD_INTERVAL
+}
+
+fn default_redirect_status() -> u16 {
+    DEFAULT_REDIRECT_STATUS
+}
+
+fn default_proxy_timeout() -> Duration {
+    DEFAULT_PROXY_TIMEOUT
+}
+
+fn deserialize_proxy<'de, D>(deserializer: D) -> Result<Option<ProxyConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Proxy {
+        Url(String),
+        Config(ProxyConfig),
+    }
+
+    let config = match Proxy::deserialize(deserializer)? {
+        Proxy::Url(url) => ProxyConfig {
+            url,
+            timeout: DEFAULT_PROXY_TIMEOUT,
+            headers: IndexMap::new(),
+            response_headers: IndexMap::new(),
+        },
+        Proxy::Config(config) => config,
+    };
+    // the upstream is reached by a plain http client
+    if !config.url.starts_with("http://") {
+        return Err(serde::de::Error::custom(format!(
+            "invalid proxy url {}, only http upstreams are supported",
+            config.url
+        )));
+    }
+
+    Ok(Some(config))
 }
 
 fn deserialize_method<'de, D>(deserializer: D) -> Result<Method, D::Error>
@@ -40,10 +480,397 @@ where
     }
 }
 
+impl ServerConfig {
+    /// relative bundle paths are resolved against the directory of the config file
+    pub fn load(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let content = std::fs::read_to_string(filename)?;
+        let mut config: Self = serde_yml::from_str(&content)?;
+
+        let base = filename.parent().unwrap_or(Path::new("."));
+        for tenant in config.tenants.iter_mut() {
+            tenant.bundle = base.join(&tenant.bundle);
+            if let Some(tls) = tenant.tls.as_mut() {
+                tls.resolve(base);
+            }
+            if let Some(canary) = tenant.canary.as_mut() {
+                canary.bundle = base.join(&canary.bundle);
+                if canary.weight > 100 {
+                    bail!("canary weight of {} must be in 0..=100", tenant.host);
+                }
+            }
+        }
+        if let Some(path) = config.access_log.as_mut().and_then(|l| l.path.as_mut()) {
+            *path = base.join(&path);
+        }
+
+        let mut hosts = HashSet::new();
+        for tenant in config.tenants.iter() {
+            for host in tenant.hosts() {
+                if !hosts.insert(tenant_key(host, tenant.mount.as_deref())) {
+                    bail!("duplicate tenant host: {}", host);
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn load_routers(&self) -> Result<Vec<TenentRouter>> {
+        self.tenants
+            .iter()
+            .map(|t| t.load_router(self.max_versions))
+            .collect()
+    }
+
+    pub fn load_certs(&self) -> Result<CertStore> {
+        let certs = CertStore::new();
+        for tenant in self.tenants.iter() {
+            if let Some(tls) = tenant.tls.as_ref() {
+                for host in tenant.hosts() {
+                    certs.insert(host, tls.clone())?;
+                }
+            }
+        }
+
+        let default = self
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.default_host.clone())
+            .or_else(|| {
+                let tenant = self.tenants.iter().find(|t| t.tls.is_some());
+                tenant.map(|t| t.host.clone())
+            });
+        if let Some(host) = default {
+            if !certs.hosts().contains(&host.to_lowercase()) {
+                bail!("no certificate for the default tls host {host}");
+            }
+            certs.set_default(&host);
+        }
+
+        Ok(certs)
+    }
+}
+
+impl TenantConfig {
+    /// the host and its aliases
+    pub fn hosts(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.host.as_str()).chain(self.aliases.iter().map(|a| a.as_str()))
+    }
+
+    pub fn load_router(&self, max_versions: usize) -> Result<TenentRouter> {
+        let router = load_bundle(&self.bundle, max_versions)?;
+        let mut tenant = TenentRouter::new(&self.host, router).with_aliases(&self.aliases);
+        if let Some(mount) = self.mount.as_ref() {
+            tenant = tenant.with_mount(mount);
+        }
+        if let Some(canary) = self.canary.as_ref() {
+            tenant = tenant.with_canary(Canary {
+                router: load_bundle(&canary.bundle, max_versions)?,
+                weight: canary.weight,
+                sticky_header: canary.sticky_header.clone(),
+            });
+        }
+
+        Ok(tenant)
+    }
+}
+
+/// the version is the file name of the bundle, which is the build hash of dino
+fn load_bundle(bundle: &Path, max_versions: usize) -> Result<SwappableAppRouter> {
+    let code = std::fs::read_to_string(bundle)?;
+    let config = ProjectConfig::load(bundle.with_extension("yml"))?;
+    let version = bundle
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let router = SwappableAppRouter::try_new_with_version(version, code, config)?
+        .with_max_versions(max_versions);
+
+    Ok(router)
+}
+
 impl ProjectConfig {
+    /// the static directories are resolved in the build directory next to the config,
+    /// e.g. `public` of `.build/{hash}.yml` is `.build/{hash}/public`
     pub fn load(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
         let content = std::fs::read_to_string(filename)?;
-        let config = serde_yml::from_str(&content)?;
+        let mut config: Self = serde_yml::from_str(&content)?;
+
+        config.check_static_dirs()?;
+        let root = filename.with_extension("");
+        for (_, statics) in config.statics.iter_mut() {
+            statics.dir = root.join(&statics.dir);
+        }
+
         Ok(config)
     }
+
+    /// the static directories must be relative without `..`, so they stay in the project
+    /// and in the build directory they are copied to
+    pub fn check_static_dirs(&self) -> Result<()> {
+        for (prefix, statics) in &self.statics {
+            let inside = statics
+                .dir
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if !inside {
+                bail!("static dir of {prefix} must be inside the project");
+            }
+        }
+        Ok(())
+    }
+
+    /// route settings take precedence over project settings
+    pub fn max_body_size(&self, route: &ProjectRoute) -> usize {
+        route
+            .max_body_size
+            .or(self.max_body_size)
+            .unwrap_or(DEFAULT_MAX_BODY_SIZE)
+    }
+
+    pub fn body_read_timeout(&self, route: &ProjectRoute) -> Duration {
+        route
+            .body_read_timeout
+            .or(self.body_read_timeout)
+            .unwrap_or(DEFAULT_BODY_READ_TIMEOUT)
+    }
+
+    pub fn rate_limit<'a>(&'a self, route: &'a ProjectRoute) -> Option<&'a RateLimitConfig> {
+        route.rate_limit.as_ref().or(self.rate_limit.as_ref())
+    }
+
+    pub fn etag(&self, route: &ProjectRoute) -> bool {
+        route.etag.unwrap_or(self.etag)
+    }
+
+    pub fn cache<'a>(&'a self, route: &'a ProjectRoute) -> Option<&'a CacheConfig> {
+        route.cache.as_ref().or(self.cache.as_ref())
+    }
+
+    pub fn max_cache_size(&self) -> usize {
+        self.max_cache_size.unwrap_or(DEFAULT_MAX_CACHE_SIZE)
+    }
+
+    /// the policy of the route replaces the one of the project
+    pub fn cors<'a>(&'a self, route: Option<&'a ProjectRoute>) -> Option<&'a CorsConfig> {
+        route.and_then(|r| r.cors.as_ref()).or(self.cors.as_ref())
+    }
+
+    /// rules between settings, checked before a version is installed
+    pub fn validate(&self) -> Result<()> {
+        if let Some(cors) = &self.cors {
+            cors.validate(&self.name)?;
+        }
+        if let Some(limit) = &self.rate_limit {
+            limit.validate(&self.name)?;
+        }
+        for (path, routes) in &self.routes {
+            for route in routes {
+                if let Some(cors) = &route.cors {
+                    cors.validate(path)?;
+                }
+                if let Some(limit) = &route.rate_limit {
+                    limit.validate(path)?;
+                }
+                // the body of a proxy route is streamed, there is no request for js
+                if let (
+                    Some(_),
+                    Some(RateLimitConfig {
+                        key: RateLimitKey::Js(_),
+                        ..
+                    }),
+                ) = (&route.proxy, self.rate_limit(route))
+                {
+                    bail!("proxy route {path} can't be rate limited by a js key");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl CorsConfig {
+    /// any site could read the responses with the cookies of the user
+    fn validate(&self, owner: &str) -> Result<()> {
+        if self.credentials && self.origins.iter().any(|o| o == "*") {
+            bail!("cors of {owner} must list its origins to allow credentials");
+        }
+        Ok(())
+    }
+}
+
+impl RateLimitConfig {
+    /// a bucket without tokens or refilled in no time limits nothing
+    fn validate(&self, owner: &str) -> Result<()> {
+        if self.requests == 0 || self.per.is_zero() {
+            bail!("rate limit of {owner} must allow some requests in a non-zero period");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_config_body_limits_should_work() {
+        let config = r#"
+        name: test
+        max_body_size: 1024
+        body_read_timeout: 5s
+        routes:
+          /upload:
+            - method: POST
+              handler: upload
+              max_body_size: 4096
+            - method: PUT
+              handler: upload
+              body_read_timeout: 1m
+        "#;
+        let config: ProjectConfig = serde_yml::from_str(config).unwrap();
+        let routes = &config.routes["/upload"];
+
+        assert_eq!(config.max_body_size(&routes[0]), 4096);
+        assert_eq!(config.body_read_timeout(&routes[0]), Duration::from_secs(5));
+        assert_eq!(config.max_body_size(&routes[1]), 1024);
+        assert_eq!(
+            config.body_read_timeout(&routes[1]),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn project_config_rate_limit_should_work() {
+        let config = r#"
+        name: test
+        rate_limit:
+          requests: 10
+          per: 1s
+        routes:
+          /api:
+            - method: GET
+              handler: api
+              rate_limit:
+                requests: 100
+                per: 1m
+                burst: 10
+                key: header:x-api-key
+            - method: POST
+              handler: api
+              rate_limit:
+                requests: 1
+                per: 1s
+                key: js:clientKey
+            - method: PUT
+              handler: api
+        "#;
+        let config: ProjectConfig = serde_yml::from_str(config).unwrap();
+        let routes = &config.routes["/api"];
+
+        let limit = config.rate_limit(&routes[0]).unwrap();
+        assert_eq!(limit.per, Duration::from_secs(60));
+        assert_eq!(limit.burst, Some(10));
+        assert_eq!(limit.key, RateLimitKey::Header("x-api-key".to_string()));
+        let limit = config.rate_limit(&routes[1]).unwrap();
+        assert_eq!(limit.key, RateLimitKey::Js("clientKey".to_string()));
+        let limit = config.rate_limit(&routes[2]).unwrap();
+        assert_eq!(limit.requests, 10);
+        assert_eq!(limit.key, RateLimitKey::Ip);
+
+        assert!(config.validate().is_ok());
+        let mut config = config;
+        config.rate_limit.as_mut().unwrap().per = Duration::ZERO;
+        assert!(config.validate().is_err());
+        config.rate_limit.as_mut().unwrap().per = Duration::from_secs(1);
+        config.rate_limit.as_mut().unwrap().requests = 0;
+        assert!(config.validate().is_err());
+
+        assert!(RateLimitKey::try_from("header:".to_string()).is_err());
+        assert!(RateLimitKey::try_from("cookie:id".to_string()).is_err());
+    }
+
+    #[test]
+    fn server_config_load_should_work() -> Result<()> {
+        let config = ServerConfig::load("fixtures/server.yml")?;
+
+        assert_eq!(config.addr, "0.0.0.0:8080".parse()?);
+        assert_eq!(config.drain_timeout, Duration::from_secs(10));
+        assert_eq!(
+            config.metrics.as_ref().unwrap().addr,
+            "127.0.0.1:9100".parse()?
+        );
+        let otel = config.otel.as_ref().unwrap();
+        assert_eq!(otel.endpoint, "http://localhost:4318");
+        assert_eq!(otel.service_name, "dino-server");
+        assert_eq!(config.probes.health, "/healthz");
+        assert_eq!(config.probes.ready, "/ready");
+        let access_log = config.access_log.as_ref().unwrap();
+        assert!(matches!(access_log.format, AccessLogFormat::Json));
+        assert_eq!(
+            access_log.path,
+            Some(PathBuf::from("fixtures/logs/access.log"))
+        );
+        assert_eq!(config.tenants.len(), 3);
+        assert_eq!(config.tenants[2].mount.as_deref(), Some("/svc/hello"));
+        assert_eq!(
+            config.tenants[0].bundle,
+            PathBuf::from("fixtures/tenants/hello.mjs")
+        );
+
+        let routers = config.load_routers()?;
+        assert_eq!(routers.len(), 3);
+        assert_eq!(routers[0].router.active_version(), "hello");
+        assert_eq!(routers[1].canary.as_ref().unwrap().weight, 5);
+
+        let tls = config.tls.as_ref().unwrap();
+        assert!(tls.redirect_http);
+        assert_eq!(tls.reload_interval, DEFAULT_CERT_RELOAD_INTERVAL);
+        let mut hosts = config.load_certs()?.hosts();
+        hosts.sort();
+        assert_eq!(hosts, ["127.0.0.1", "dino.local", "localhost"]);
+        assert_eq!(
+            config.load_certs()?.default_host().as_deref(),
+            Some("localhost")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn project_config_should_reject_static_dir_outside_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("v1.yml");
+        for static_dir in ["../secret", "/etc"] {
+            let config =
+                format!("name: a\nstatic:\n  /assets:\n    dir: {static_dir}\nroutes: {{}}\n");
+            std::fs::write(&filename, config).unwrap();
+            assert!(ProjectConfig::load(&filename).is_err());
+        }
+
+        let config = "name: a\nstatic:\n  /assets:\n    dir: public\nroutes: {}\n";
+        std::fs::write(&filename, config).unwrap();
+        let config = ProjectConfig::load(&filename).unwrap();
+        assert_eq!(config.statics["/assets"].dir, dir.path().join("v1/public"));
+    }
+
+    #[test]
+    fn server_config_should_reject_duplicate_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("server.yml");
+        let config = "tenants:\n  - host: a\n    bundle: a.mjs\n  - host: a\n    bundle: b.mjs\n";
+        std::fs::write(&filename, config).unwrap();
+        assert!(ServerConfig::load(&filename).is_err());
+
+        let config = "tenants:\n  - host: a\n    bundle: a.mjs\n  - host: b\n    aliases: [A]\n    bundle: b.mjs\n";
+        std::fs::write(&filename, config).unwrap();
+        assert!(ServerConfig::load(&filename).is_err());
+
+        let config = "tenants:\n  - host: a\n    bundle: a.mjs\n  - host: a\n    mount: /b\n    bundle: b.mjs\n";
+        std::fs::write(&filename, config).unwrap();
+        assert!(ServerConfig::load(&filename).is_ok());
+    }
 }
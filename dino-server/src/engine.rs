@@ -1,8 +1,9 @@
 use anyhow::Result;
 use axum::{body::Body, response::Response};
 use dino_macros::{FromJs, IntoJs};
-use rquickjs::{Context, Function, Object, Promise, Runtime};
+use rquickjs::{Context, Ctx, Function, IntoJs, Object, Promise, Runtime, TypedArray, Value};
 use std::collections::HashMap;
+use tracing::instrument;
 use typed_builder::TypedBuilder;
 
 #[allow(unused)]
@@ -11,7 +12,7 @@ pub struct JsWorker {
     ctx: Context,
 }
 
-#[derive(Debug, TypedBuilder, IntoJs)]
+#[derive(Debug, Clone, TypedBuilder, IntoJs)]
 pub struct Req {
     #[builder(setter(into))]
     pub method: String,
@@ -25,9 +26,40 @@ pub struct Req {
     pub headers: HashMap<String, String>,
     #[builder(default)]
     pub body: Option<String>,
+    #[builder(default)]
+    pub form: Option<Vec<FormPart>>,
+    /// path prefix the tenant is mounted at, the params are matched after it
+    #[builder(default, setter(into))]
+    pub mount: Option<String>,
+    /// the `x-request-id` of the request, generated if the client did not send one
+    #[builder(default, setter(into))]
+    pub request_id: Option<String>,
+    /// w3c trace context to send with outgoing requests, so they join the trace
+    #[builder(default, setter(into))]
+    pub traceparent: Option<String>,
+}
+
+/// a field or file of a `multipart/form-data` body
+#[derive(Debug, Clone, TypedBuilder, IntoJs)]
+pub struct FormPart {
+    #[builder(setter(into))]
+    pub name: String,
+    #[builder(default)]
+    pub filename: Option<String>,
+    #[builder(default)]
+    pub content_type: Option<String>,
+    /// utf-8 content of a plain field, `None` for files
+    #[builder(default)]
+    pub value: Option<String>,
+    #[builder(setter(into))]
+    pub data: Buffer,
 }
 
-#[derive(Debug, FromJs)]
+/// raw bytes exposed to js as an `Uint8Array`
+#[derive(Debug, Clone, Default)]
+pub struct Buffer(pub Vec<u8>);
+
+#[derive(Debug, Clone, FromJs)]
 pub struct Res {
     pub headers: HashMap<String, String>,
     pub status: u16,
@@ -53,6 +85,12 @@ impl JsWorker {
         Ok(Self { rt, ctx })
     }
 
+    /// bytes used by the quickjs heap
+    pub fn memory_used(&self) -> i64 {
+        self.rt.memory_usage().memory_used_size
+    }
+
+    #[instrument(skip(self, req))]
     pub fn run(&self, name: &str, req: Req) -> Result<Res> {
         self.ctx.with(|ctx| {
             let global = ctx.globals();
@@ -63,6 +101,32 @@ impl JsWorker {
             Ok::<_, anyhow::Error>(v.finish()?)
         })
     }
+
+    /// call a js function returning a string, e.g. the rate limit key of a request
+    pub fn run_key(&self, name: &str, req: Req) -> Result<String> {
+        self.ctx.with(|ctx| {
+            let global = ctx.globals();
+            let handlers: Object = global.get("handlers")?;
+            let fun: Function = handlers.get(name)?;
+            let v: Value = fun.call((req,))?;
+            let key = match v.as_promise() {
+                Some(promise) => promise.finish()?,
+                None => v.get()?,
+            };
+
+            Ok::<_, anyhow::Error>(key)
+        })
+    }
+}
+
+impl Res {
+    /// the headers of a js response may have any case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
 }
 
 impl From<Res> for Response {
@@ -81,6 +145,18 @@ impl From<Res> for Response {
     }
 }
 
+impl From<Vec<u8>> for Buffer {
+    fn from(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+}
+
+impl<'js> IntoJs<'js> for Buffer {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        TypedArray::<u8>::new(ctx.clone(), self.0)?.into_js(ctx)
+    }
+}
+
 fn print(msg: String) {
     println!("{msg}");
 }
@@ -116,4 +192,44 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn js_worker_should_receive_form() -> Result<()> {
+        let code = r#"
+        (function(){
+            async function upload(req){
+                let file = req.form[1];
+                return {
+                    status: 200,
+                    headers: {},
+                    body: `${req.form[0].value}:${file.filename}:${file.content_type}:${file.data.length}:${file.data[0]}`,
+                };
+            }
+            return{upload:upload};
+        })();
+        "#;
+        let form = vec![
+            FormPart::builder()
+                .name("title")
+                .value(Some("hello".to_string()))
+                .data(b"hello".to_vec())
+                .build(),
+            FormPart::builder()
+                .name("file")
+                .filename(Some("a.txt".to_string()))
+                .content_type(Some("text/plain".to_string()))
+                .data(b"abc".to_vec())
+                .build(),
+        ];
+        let req = Req::builder()
+            .method("POST")
+            .url("/upload")
+            .form(Some(form))
+            .build();
+        let worker = JsWorker::try_new(code)?;
+        let res = worker.run("upload", req)?;
+
+        assert_eq!(res.body.as_deref(), Some("hello:a.txt:text/plain:3:97"));
+        Ok(())
+    }
 }
@@ -1,5 +1,5 @@
 use axum::{
-    http::{Method, StatusCode},
+    http::{header::RETRY_AFTER, Method, StatusCode},
     response::IntoResponse,
 };
 use thiserror::Error;
@@ -16,11 +16,44 @@ pub enum AppError {
     #[error("Route method not found: {0}")]
     RouteMethodNotAllowed(Method),
 
+    #[error("Version not found: {0}")]
+    VersionNotFound(String),
+
+    #[error("Invalid bundle: {0}")]
+    InvalidBundle(String),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
     #[error("Anyhow error: {0}")]
     Anyhow(#[from] anyhow::Error),
 
     #[error("Serde error: {0}")]
     Serde(#[from] serde_json::Error),
+
+    #[error("Payload too large: body exceeds {0} bytes")]
+    PayloadTooLarge(usize),
+
+    #[error("Request timeout: body not received within {0:?}")]
+    RequestTimeout(std::time::Duration),
+
+    #[error("Yaml error: {0}")]
+    Yaml(#[from] serde_yml::Error),
+
+    #[error("Multipart error: {0}")]
+    Multipart(#[from] multer::Error),
+
+    #[error("Too many requests: retry after {0:?}")]
+    TooManyRequests(std::time::Duration),
+
+    #[error("Bad gateway: {0}")]
+    BadGateway(String),
+
+    #[error("Gateway timeout: upstream did not respond within {0:?}")]
+    GatewayTimeout(std::time::Duration),
 }
 
 impl IntoResponse for AppError {
@@ -29,10 +62,31 @@ impl IntoResponse for AppError {
             AppError::HostNotFound(_) => StatusCode::NOT_FOUND,
             AppError::RoutePathNotFound(_) => StatusCode::NOT_FOUND,
             AppError::RouteMethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::VersionNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InvalidBundle(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidConfig(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
             AppError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Serde(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Yaml(_) => StatusCode::BAD_REQUEST,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::RequestTimeout(_) => StatusCode::REQUEST_TIMEOUT,
+            AppError::Multipart(multer::Error::FieldSizeExceeded { .. })
+            | AppError::Multipart(multer::Error::StreamSizeExceeded { .. }) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            AppError::Multipart(_) => StatusCode::BAD_REQUEST,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            AppError::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
         };
 
-        (code, self.to_string()).into_response()
+        let mut res = (code, self.to_string()).into_response();
+        if let AppError::TooManyRequests(retry_after) = self {
+            // whole seconds, rounded up so the client does not retry too early
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            res.headers_mut().insert(RETRY_AFTER, secs.max(1).into());
+        }
+        res
     }
 }
@@ -1,70 +1,443 @@
-use crate::{error::AppError, AppRouter, AppState, JsWorker, Req};
+use crate::{
+    assets::serve_static,
+    conditional::{add_etag, not_modified},
+    error::AppError,
+    metrics,
+    multipart::{get_boundary, parse_multipart},
+    tenant::tenant_key,
+    traceparent, AppRouter, AppState, CacheStatus, FormPart, JsWorker, ProjectRoute,
+    RateLimitConfig, RateLimitKey, Req, RequestId, Res, RouteInfo, Selected, VARIANT_COOKIE,
+};
 use axum::{
-    body::Bytes,
-    extract::{Query, State},
-    http::{request::Parts, Response},
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Query, State},
+    http::{
+        header::{AGE, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, SET_COOKIE},
+        request::Parts,
+        HeaderMap, Method, Response, StatusCode,
+    },
     response::IntoResponse,
 };
 use axum_extra::extract::Host;
+use http_body_util::LengthLimitError;
 use matchit::Match;
-use std::collections::HashMap;
-use tracing::info;
+use std::{collections::HashMap, net::SocketAddr};
+use tokio::time::{timeout, Instant};
+use tracing::{info, instrument, warn};
+
+/// the active version of the tenant which served the request
+const VERSION_HEADER: &str = "x-dino-version";
+/// the variant (primary or canary) which served the request
+const VARIANT_HEADER: &str = "x-dino-variant";
+/// `HIT`, `STALE` or `MISS` for cacheable requests
+const CACHE_HEADER: &str = "x-dino-cache";
+/// handler of static files in the access log and the metrics
+const STATIC_HANDLER: &str = "static";
+/// handler of proxy routes in the access log and the metrics
+const PROXY_HANDLER: &str = "proxy";
+/// handler of redirects in the access log and the metrics
+const REDIRECT_HANDLER: &str = "redirect";
 
 /// we only support requests and return JSON responses
 /// get router from state
 /// match router with parts.path fer a handler
 /// convert request data into Req and call handler with a js runtime
 /// convert Req into response and return
-#[allow(unused)]
 pub(crate) async fn handler(
     State(state): State<AppState>,
     parts: Parts,
-    Host(mut host): Host,
+    Host(host): Host,
     Query(query): Query<HashMap<String, String>>,
-    body: Bytes,
-) -> Result<impl IntoResponse, AppError> {
+    body: Body,
+) -> Response<Body> {
+    // error responses are labelled by the tenant and the route known when they failed
+    let mut info = None;
+    let mut res = serve(state, parts, host, query, body, &mut info)
+        .await
+        .into_response();
+    if let Some(info) = info {
+        res.extensions_mut().insert(info);
+    }
+    res
+}
+
+#[allow(unused)]
+async fn serve(
+    state: AppState,
+    parts: Parts,
+    host: String,
+    query: HashMap<String, String>,
+    body: Body,
+    info: &mut Option<RouteInfo>,
+) -> Result<Response<Body>, AppError> {
     // info!("state: {:?}", state);
     // info!("parts: {:?}", parts);
     // info!("query: {:?}", query);
     // info!("body: {:?}", body);
     // info!("host: {:?}", host);
 
-    let router = get_router_by_host(host, state)?;
+    let (selected, mount) = get_router_by_host(host.clone(), &parts, state.clone())?;
+    let router = selected.router;
+    let route_info = |route: &str, handler: &str| {
+        Some(RouteInfo {
+            tenant: router.config.name.clone(),
+            route: route.to_string(),
+            handler: handler.to_string(),
+        })
+    };
+    *info = route_info("", "");
+
+    // the routes of a mounted tenant are relative to its mount point
+    let path = match &parts.uri.path()[mount.len()..] {
+        "" => "/",
+        path => path,
+    };
 
-    let matched = router.match_it(parts.method.clone(), parts.uri.path())?;
+    // redirects and rewrites are evaluated before static files and routes
+    if let Some((location, redirect)) = router.redirect(path, parts.uri.query(), mount) {
+        *info = route_info(&redirect.path, REDIRECT_HANDLER);
+        let res = Response::builder()
+            .status(redirect.status)
+            .header(LOCATION, location)
+            .body(Body::empty())
+            .map_err(|e| AppError::Anyhow(e.into()))?;
+        return Ok(res);
+    }
+    let path = router.rewrite(path);
+    let path = path.as_ref();
 
-    let handler = matched.value;
+    // the routes are used for paths without a static file
+    if let Some((prefix, res)) = serve_static(&router.config, path, &parts).await {
+        *info = route_info(&prefix, STATIC_HANDLER);
+        return Ok(res);
+    }
 
-    let req = assemble_req(&matched, &parts, body, query)?;
+    let matched = router.match_it(parts.method.clone(), path)?;
 
-    // call handler with req
-    // TODO: build worker pool, and send req vis mpsc channel and get res from oneshot channel
-    let work = JsWorker::try_new(&router.code)?;
-    let res = work.run(handler, req)?;
+    let route = matched.value;
+    *info = match route.proxy {
+        Some(_) => route_info(&route.path, PROXY_HANDLER),
+        None => route_info(&route.path, &route.handler),
+    };
+
+    // keys from the connection or the headers are checked before the body is read
+    let rate_limit = router.config.rate_limit(route);
+    if let Some(limit) = rate_limit {
+        if let Some(key) = client_key(limit, &parts) {
+            check_rate_limit(&state, &router, route, limit, &key)?;
+        }
+    }
+
+    let max_body_size = router.config.max_body_size(route);
+    let read_timeout = router.config.body_read_timeout(route);
+    // the body is streamed to the upstream, js keys of a rate limit are rejected by the config
+    if let Some(proxy) = &route.proxy {
+        let params = matched
+            .params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let res = state
+            .proxy
+            .forward(
+                proxy,
+                &params,
+                path,
+                &parts,
+                body,
+                max_body_size,
+                read_timeout,
+            )
+            .await?;
+        return Ok(res);
+    }
+
+    let (body, form) = timeout(read_timeout, read_body(&parts, body, route, max_body_size))
+        .await
+        .map_err(|_| AppError::RequestTimeout(read_timeout))??;
+
+    let mut req = assemble_req(&matched, &parts, body, form, query)?;
+    if !mount.is_empty() {
+        req.mount = Some(mount.to_string());
+    }
+    // taken in the request span, so outgoing requests of the handler are its children
+    req.traceparent = traceparent(&parts.headers);
+
+    // the worker computing the key also runs the handler, the bundle is evaluated once
+    let mut worker = None;
+    if let Some(
+        limit @ RateLimitConfig {
+            key: RateLimitKey::Js(name),
+            ..
+        },
+    ) = rate_limit
+    {
+        let work = JsWorker::try_new(&router.code)?;
+        let key = work.run_key(name, req.clone())?;
+        check_rate_limit(&state, &router, route, limit, &key)?;
+        worker = Some(work);
+    }
+
+    // responses of GET requests are cached by the active version of the tenant, HEAD requests
+    // answered by the GET handler only read them
+    let is_get = parts.method == Method::GET;
+    let cacheable = is_get || (parts.method == Method::HEAD && route.method == Method::GET);
+    let cache_key = cacheable.then(|| cache_key(&host, mount, &parts));
+    let cached = cache_key
+        .as_ref()
+        .filter(|_| !bypass_cache(&parts.headers))
+        .and_then(|key| router.cache.get(key, &parts.headers));
+    let (res, cache) = match (cached, cache_key) {
+        (Some(cached), Some(key)) => {
+            if cached.revalidate && is_get {
+                revalidate(&router, route, req, key, parts.headers.clone());
+            } else if cached.revalidate {
+                router.cache.release(&key, &parts.headers);
+            }
+            (cached.res, Some((cached.status, Some(cached.age))))
+        }
+        (_, key) => {
+            let res = execute(&router, route, req, worker)?;
+            if let Some(key) = key.as_ref().filter(|_| is_get) {
+                let config = router.config.cache(route);
+                router.cache.put(key, &parts.headers, &res, config);
+            }
+            (res, key.map(|_| (CacheStatus::Miss, None)))
+        }
+    };
+
+    let mut res = res;
+    let conditional = is_get || parts.method == Method::HEAD;
+    if conditional && not_modified(&parts.headers, &res) {
+        res.status = StatusCode::NOT_MODIFIED.as_u16();
+        res.body = None;
+    } else if parts.method == Method::HEAD {
+        let len = res.body.take().map(|b| b.len()).unwrap_or_default();
+        res.headers
+            .retain(|k, _| !k.eq_ignore_ascii_case(CONTENT_LENGTH.as_str()));
+        res.headers
+            .insert(CONTENT_LENGTH.to_string(), len.to_string());
+    }
 
     // covert Req into response and return
-    Ok(Response::from(res))
+    let mut res = Response::from(res);
+    if let Some((status, age)) = cache {
+        res.headers_mut()
+            .insert(CACHE_HEADER, status.as_str().parse().unwrap());
+        if let Some(age) = age {
+            res.headers_mut().insert(AGE, age.as_secs().into());
+        }
+    }
+    if let Ok(version) = router.version.parse() {
+        res.headers_mut().insert(VERSION_HEADER, version);
+    }
+    let variant = selected.variant;
+    res.headers_mut()
+        .insert(VARIANT_HEADER, variant.as_str().parse().unwrap());
+    if selected.new_assignment {
+        // tenants mounted on the same host keep their own variant
+        let path = if mount.is_empty() { "/" } else { mount };
+        let cookie = format!("{VARIANT_COOKIE}={variant}; Path={path}; HttpOnly");
+        res.headers_mut()
+            .append(SET_COOKIE, cookie.parse().unwrap());
+    }
+    if let Some(compression) = &router.config.compression {
+        res.extensions_mut().insert(compression.clone());
+    }
+    Ok(res)
 }
 
+/// call the handler of the route with req, on the given worker or a new one
+// TODO: build worker pool, and send req vis mpsc channel and get res from oneshot channel
+fn execute(
+    router: &AppRouter,
+    route: &ProjectRoute,
+    req: Req,
+    worker: Option<JsWorker>,
+) -> Result<Res, AppError> {
+    let metrics = metrics();
+    let tenant = router.config.name.as_str();
+    metrics.js_workers_active.inc();
+    let start = Instant::now();
+    let worker = match worker {
+        Some(work) => Ok(work),
+        None => JsWorker::try_new(&router.code),
+    };
+    let ret = worker.and_then(|work| {
+        let res = work.run(&route.handler, req);
+        metrics
+            .js_heap_bytes
+            .with_label_values(&[tenant])
+            .observe(work.memory_used() as f64);
+        res
+    });
+    metrics
+        .js_execution_duration
+        .with_label_values(&[tenant, route.handler.as_str()])
+        .observe(start.elapsed().as_secs_f64());
+    metrics.js_workers_active.dec();
+
+    let mut res = ret?;
+    if router.config.etag(route) {
+        add_etag(&mut res);
+    }
+
+    Ok(res)
+}
+
+/// refresh a stale response in the background, the client gets the stale one
+fn revalidate(router: &AppRouter, route: &ProjectRoute, req: Req, key: String, headers: HeaderMap) {
+    let router = router.clone();
+    let route = route.clone();
+    tokio::task::spawn_blocking(move || match execute(&router, &route, req, None) {
+        Ok(res) => {
+            let config = router.config.cache(&route);
+            router.cache.put(&key, &headers, &res, config);
+        }
+        Err(e) => {
+            warn!("failed to revalidate {key}: {e}");
+            router.cache.release(&key, &headers);
+        }
+    });
+}
+
+/// the host and the uri of the request, variants by `vary` are kept under the same key
+///
+/// tenants with wildcard hosts or aliases serve many hosts with one router and one cache
+fn cache_key(host: &str, mount: &str, parts: &Parts) -> String {
+    let host = &host[..host.find(':').unwrap_or(host.len())];
+    let uri = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    format!("GET {} {uri}", tenant_key(host, Some(mount)))
+}
+
+/// clients asking for a fresh response are not served from the cache
+fn bypass_cache(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|d| matches!(d.trim(), "no-cache" | "no-store" | "max-age=0"))
+}
+
+/// the selected router and the mount point of its tenant, empty if mounted at the root
 #[allow(unused_must_use)]
-fn get_router_by_host(mut host: String, state: AppState) -> Result<AppRouter, AppError> {
+#[instrument(skip(parts, state))]
+fn get_router_by_host(
+    mut host: String,
+    parts: &Parts,
+    state: AppState,
+) -> Result<(Selected, &str), AppError> {
     host.split_off(host.find(":").unwrap_or(host.len()));
 
     info!("Introduction host: {:?}", host);
 
-    let router = state
-        .routes
-        .get(&host)
-        .ok_or(AppError::HostNotFound(host))?
-        .load();
+    let (tenant, mount) = state
+        .find_tenant(&host, parts.uri.path())
+        .ok_or(AppError::HostNotFound(host))?;
+
+    // the cors middleware may have picked the variant already
+    let selected = match parts.extensions.get::<Selected>() {
+        Some(selected) => selected.clone(),
+        None => tenant.select(&parts.headers),
+    };
+
+    Ok((selected, mount))
+}
+
+/// the client key of the limit, `None` if it is computed by js or the client is unknown
+///
+/// a router served without `ConnectInfo` has no ip, such clients are not limited rather
+/// than sharing one bucket
+fn client_key(limit: &RateLimitConfig, parts: &Parts) -> Option<String> {
+    let ip = || {
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| format!("ip:{}", info.0.ip()));
+        if ip.is_none() {
+            warn!("no client ip, the rate limit is skipped");
+        }
+        ip
+    };
+    match &limit.key {
+        RateLimitKey::Ip => ip(),
+        RateLimitKey::Header(name) => match parts.headers.get(name).and_then(|v| v.to_str().ok()) {
+            Some(value) => Some(format!("key:{value}")),
+            None => ip(),
+        },
+        RateLimitKey::Js(_) => None,
+    }
+}
 
-    Ok(router)
+/// every route of a tenant has its own buckets
+fn check_rate_limit(
+    state: &AppState,
+    router: &AppRouter,
+    route: &ProjectRoute,
+    limit: &RateLimitConfig,
+    key: &str,
+) -> Result<(), AppError> {
+    let key = format!(
+        "{}:{}:{}:{}",
+        router.config.name, route.method, route.path, key
+    );
+    state
+        .rate_limiter
+        .check(&key, limit)
+        .map_err(AppError::TooManyRequests)
 }
 
+/// multipart bodies are parsed into form parts, other bodies are kept as utf-8 string
+async fn read_body(
+    parts: &Parts,
+    body: Body,
+    route: &ProjectRoute,
+    max_body_size: usize,
+) -> Result<(Option<String>, Option<Vec<FormPart>>), AppError> {
+    // reject early if the client announces a body that is too large
+    let content_length = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if content_length.is_some_and(|len| len > max_body_size) {
+        return Err(AppError::PayloadTooLarge(max_body_size));
+    }
+
+    let boundary = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(get_boundary);
+
+    if let Some(boundary) = boundary {
+        let form = parse_multipart(body, boundary, &route.multipart, max_body_size).await?;
+        return Ok((None, Some(form)));
+    }
+
+    let body = to_bytes(body, max_body_size).await.map_err(|e| {
+        let e = e.into_inner();
+        if e.is::<LengthLimitError>() {
+            AppError::PayloadTooLarge(max_body_size)
+        } else {
+            AppError::Anyhow(anyhow::anyhow!(e))
+        }
+    })?;
+
+    Ok((String::from_utf8(body.to_vec()).ok(), None))
+}
+
+#[instrument(skip_all)]
 fn assemble_req(
-    matched: &Match<&str>,
+    matched: &Match<&ProjectRoute>,
     parts: &Parts,
-    body: Bytes,
+    body: Option<String>,
+    form: Option<Vec<FormPart>>,
     query: HashMap<String, String>,
 ) -> Result<Req, AppError> {
     let params = matched
@@ -79,8 +452,6 @@ fn assemble_req(
         .map(|(k, v)| (k.to_string(), v.to_str().unwrap().to_string()))
         .collect::<HashMap<_, _>>();
 
-    let body = String::from_utf8(body.to_vec()).ok();
-
     let req = Req::builder()
         .method(parts.method.to_string())
         .url(parts.uri.to_string())
@@ -88,7 +459,299 @@ fn assemble_req(
         .query(query)
         .params(params)
         .body(body)
+        .form(form)
+        .request_id(parts.extensions.get::<RequestId>().map(|id| id.0.clone()))
         .build();
 
     Ok(req)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    fn route() -> ProjectRoute {
+        serde_yml::from_str("method: POST\nhandler: hello").unwrap()
+    }
+
+    #[tokio::test]
+    async fn read_body_should_work() {
+        let (parts, body) = Request::new(Body::from("hello")).into_parts();
+        let (body, form) = read_body(&parts, body, &route(), 5).await.unwrap();
+
+        assert_eq!(body.as_deref(), Some("hello"));
+        assert!(form.is_none());
+    }
+
+    #[tokio::test]
+    async fn read_body_should_reject_large_body() {
+        let (parts, body) = Request::new(Body::from("hello world")).into_parts();
+        let ret = read_body(&parts, body, &route(), 5).await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(5))));
+
+        let (parts, body) = Request::builder()
+            .header(CONTENT_LENGTH, "1024")
+            .body(Body::empty())
+            .unwrap()
+            .into_parts();
+        let ret = read_body(&parts, body, &route(), 5).await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(5))));
+    }
+
+    #[tokio::test]
+    async fn rate_limit_should_work() -> anyhow::Result<()> {
+        use crate::{DinoServer, ProjectConfig, SwappableAppRouter, TenentRouter};
+        use axum::http::{header::RETRY_AFTER, StatusCode};
+        use tower::ServiceExt;
+
+        let code = r#"(function(){
+            return {
+                hello: async (req) => ({ status: 200, headers: {}, body: "hello" }),
+                clientKey: (req) => req.query.user,
+            };
+        })()"#;
+        let config = r#"
+        name: limited
+        routes:
+          /header:
+            - method: GET
+              handler: hello
+              rate_limit:
+                requests: 1
+                per: 1m
+                key: header:x-api-key
+          /js:
+            - method: GET
+              handler: hello
+              rate_limit:
+                requests: 1
+                per: 1m
+                key: js:clientKey
+        "#;
+        let config: ProjectConfig = serde_yml::from_str(config)?;
+        let router = SwappableAppRouter::try_new(code, config)?;
+        let app = DinoServer::new(vec![TenentRouter::new("localhost", router)]).router();
+        let status = |uri: &str, key: &str| {
+            let req = Request::builder()
+                .uri(uri)
+                .header("host", "localhost")
+                .header("x-api-key", key)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap() }
+        };
+
+        assert_eq!(status("/header", "a").await.status(), StatusCode::OK);
+        let res = status("/header", "a").await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[RETRY_AFTER], "60");
+        // errors are counted and logged by their route
+        let info = res.extensions().get::<RouteInfo>().unwrap();
+        assert_eq!(
+            (info.tenant.as_str(), info.route.as_str()),
+            ("limited", "/header")
+        );
+        assert_eq!(info.handler, "hello");
+        let res = status("/missing", "a").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let info = res.extensions().get::<RouteInfo>().unwrap();
+        assert_eq!((info.tenant.as_str(), info.route.as_str()), ("limited", ""));
+        assert_eq!(status("/header", "b").await.status(), StatusCode::OK);
+
+        assert_eq!(status("/js?user=a", "").await.status(), StatusCode::OK);
+        assert_eq!(
+            status("/js?user=a", "").await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(status("/js?user=b", "").await.status(), StatusCode::OK);
+
+        // without the header the ip is the key, without the ip the client is not limited
+        let send = |ip: Option<[u8; 4]>| {
+            let mut req = Request::builder()
+                .uri("/header")
+                .header("host", "localhost")
+                .body(Body::empty())
+                .unwrap();
+            if let Some(ip) = ip {
+                let addr = SocketAddr::from((ip, 1234));
+                req.extensions_mut().insert(ConnectInfo(addr));
+            }
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap().status() }
+        };
+        assert_eq!(send(Some([10, 0, 0, 1])).await, StatusCode::OK);
+        assert_eq!(
+            send(Some([10, 0, 0, 1])).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(send(Some([10, 0, 0, 2])).await, StatusCode::OK);
+        assert_eq!(send(None).await, StatusCode::OK);
+        assert_eq!(send(None).await, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn response_cache_should_work() -> anyhow::Result<()> {
+        use crate::{DinoServer, ProjectConfig, SwappableAppRouter, TenentRouter};
+        use http_body_util::BodyExt;
+        use tower::ServiceExt;
+
+        let code = r#"(function(){
+            return {
+                hello: async (req) => ({ status: 200, headers: {}, body: `${Math.random()}` }),
+                head: async (req) => ({ status: 200, headers: { "x-head": "1" } }),
+            };
+        })()"#;
+        let config = r#"
+        name: cached
+        routes:
+          /cached:
+            - method: GET
+              handler: hello
+              cache:
+                ttl: 1m
+          /own:
+            - method: GET
+              handler: hello
+              cache:
+                ttl: 1m
+            - method: HEAD
+              handler: head
+        "#;
+        let config: ProjectConfig = serde_yml::from_str(config)?;
+        let router = SwappableAppRouter::try_new(code, config.clone())?;
+        let app = DinoServer::new(vec![TenentRouter::new("localhost", router.clone())]).router();
+        let send = |method: Method, uri: &str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("host", "localhost")
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                let cache = res
+                    .headers()
+                    .get(CACHE_HEADER)
+                    .map(|v| v.to_str().unwrap().to_string())
+                    .unwrap_or_default();
+                let body = res.into_body().collect().await.unwrap().to_bytes();
+                (cache, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        let get = |uri: &str| send(Method::GET, uri);
+
+        let (cache, body) = get("/cached").await;
+        assert_eq!(cache, "MISS");
+        assert_eq!(get("/cached").await, ("HIT".to_string(), body.clone()));
+        // the query is part of the key
+        assert_eq!(get("/cached?a=1").await.0, "MISS");
+
+        // hosts of the same tenant have their own responses
+        let req = Request::builder()
+            .uri("/cached")
+            .header("host", "LocalHost.:8080")
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.headers()[CACHE_HEADER], "HIT");
+        let key = |host: &str| {
+            let (parts, _) = Request::builder()
+                .uri("/cached")
+                .body(())
+                .unwrap()
+                .into_parts();
+            cache_key(host, "", &parts)
+        };
+        assert_eq!(key("localhost"), "GET localhost /cached");
+        assert_ne!(key("a.localhost"), key("b.localhost"));
+
+        // head requests only read the responses of the get handler
+        assert_eq!(send(Method::HEAD, "/cached?b=1").await.0, "MISS");
+        assert_eq!(get("/cached?b=1").await.0, "MISS");
+        assert_eq!(send(Method::HEAD, "/cached?b=1").await.0, "HIT");
+        assert_eq!(send(Method::HEAD, "/own").await.0, "");
+        let (cache, body) = get("/own").await;
+        assert_eq!(cache, "MISS");
+        assert!(!body.is_empty());
+        assert_eq!(send(Method::HEAD, "/own").await.0, "");
+
+        // new code drops the responses of the old one
+        router.swap(format!("{code};"), config)?;
+        let (cache, new_body) = get("/cached").await;
+        assert_eq!(cache, "MISS");
+        assert_ne!(new_body, body);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn conditional_request_should_work() -> anyhow::Result<()> {
+        use crate::{DinoServer, ProjectConfig, SwappableAppRouter, TenentRouter};
+        use axum::http::header::{ETAG, IF_NONE_MATCH};
+        use http_body_util::BodyExt;
+        use tower::ServiceExt;
+
+        let code = r#"(function(){
+            return {
+                hello: async (req) => ({ status: 200, headers: {}, body: "hello" }),
+                tagged: async (req) => ({ status: 200, headers: { ETag: '"v1"' }, body: "hello" }),
+            };
+        })()"#;
+        let config = r#"
+        name: conditional
+        etag: true
+        routes:
+          /hello:
+            - method: GET
+              handler: hello
+          /tagged:
+            - method: GET
+              handler: tagged
+              etag: false
+        "#;
+        let config: ProjectConfig = serde_yml::from_str(config)?;
+        let router = SwappableAppRouter::try_new(code, config)?;
+        let app = DinoServer::new(vec![TenentRouter::new("localhost", router)]).router();
+        let send = |method: Method, uri: &str, etag: Option<&str>| {
+            let mut req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("host", "localhost");
+            if let Some(etag) = etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            let req = req.body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(req).await.unwrap();
+                let (parts, body) = res.into_parts();
+                let body = body.collect().await.unwrap().to_bytes();
+                (parts, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let (parts, body) = send(Method::GET, "/hello", None).await;
+        assert_eq!(body, "hello");
+        let etag = parts.headers[ETAG].to_str()?.to_string();
+        let (parts, body) = send(Method::GET, "/hello", Some(&etag)).await;
+        assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(body, "");
+
+        let (parts, _) = send(Method::GET, "/tagged", Some("\"v1\"")).await;
+        assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(parts.headers[ETAG], "\"v1\"");
+
+        // the get handler answers head requests without a body
+        let (parts, body) = send(Method::HEAD, "/hello", None).await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(parts.headers[CONTENT_LENGTH], "5");
+        assert_eq!(parts.headers[ETAG], etag.as_str());
+        assert_eq!(body, "");
+
+        Ok(())
+    }
+}
@@ -1,59 +1,155 @@
+mod admin;
+mod assets;
+mod cache;
+mod conditional;
 mod config;
 mod engine;
 mod error;
 mod handler;
+mod metrics;
 mod middleware;
+mod multipart;
+mod probe;
+mod proxy;
+mod rate_limit;
 mod router;
+mod server;
+mod shutdown;
+mod tenant;
+mod tls;
+mod trace;
 
+pub use admin::{
+    admin_router, start_admin_server, CanaryDeployment, CanaryInfo, Deployment, TenantInfo,
+    DEFAULT_ADMIN_MAX_BODY_SIZE,
+};
+pub use cache::{CacheStatus, Cached, ResponseCache};
 pub use config::*;
 pub use engine::*;
 pub use error::AppError;
+pub use metrics::{metrics, metrics_router, spawn_metrics_server, Metrics};
 pub use middleware::*;
+pub use probe::{probe_router, ServerInfo};
+pub use proxy::ProxyClient;
+pub use rate_limit::RateLimiter;
 pub use router::*;
+pub use server::DinoServer;
+pub use shutdown::{shutdown_signal, ServerHandle, DEFAULT_DRAIN_TIMEOUT};
+pub use tenant::*;
+pub use tls::{redirect_router, spawn_redirect_server, CertConfig, CertStore, TlsListener};
+#[cfg(feature = "otel")]
+pub use trace::otel_layer;
+pub use trace::{init_tracing, traceparent, TracingGuard, TRACEPARENT_HEADER};
 
-use axum::{routing::any, Router};
 use dashmap::DashMap;
-use handler::handler;
-use tokio::net::TcpListener;
-use tracing::info;
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, Clone)]
 pub struct AppState {
-    // key is hostname
-    routes: DashMap<String, SwappableAppRouter>,
+    // key is hostname, shared with the admin api
+    routes: Arc<DashMap<String, Tenant>>,
+    // versions kept by tenants created through the admin api
+    max_versions: usize,
+    // if a bundle evaluated, by the hash of its code, shared by all clones
+    evaluated: Arc<DashMap<String, bool>>,
+    started_at: Instant,
+    rate_limiter: RateLimiter,
+    proxy: ProxyClient,
 }
 
 #[derive(Debug, Clone)]
 pub struct TenentRouter {
     host: String,
     router: SwappableAppRouter,
+    canary: Option<Canary>,
+    // other hosts served by the same tenant
+    aliases: Vec<String>,
+    // path prefix on the hosts, the tenant serves the whole host if not set
+    mount: Option<String>,
 }
 
 pub async fn start_server(port: u16, routers: Vec<TenentRouter>) -> anyhow::Result<()> {
-    let addr = format!("0.0.0.0:{port}");
-    let listener = TcpListener::bind(&addr).await?;
-    info!("Listening on: {}", addr);
+    start_server_with_addr(SocketAddr::from(([0, 0, 0, 0], port)), routers).await
+}
 
-    let routers = routers
-        .into_iter()
-        .map(|t| (t.host, t.router))
-        .collect::<DashMap<_, _>>();
+pub async fn start_server_with_addr(
+    addr: SocketAddr,
+    routers: Vec<TenentRouter>,
+) -> anyhow::Result<()> {
+    start_server_with_state(addr, routers.into()).await
+}
 
-    let state = AppState::new(routers);
+/// serve until SIGINT or SIGTERM, then drain in-flight requests
+pub async fn start_server_with_state(addr: SocketAddr, state: AppState) -> anyhow::Result<()> {
+    spawn_server(addr, state, DEFAULT_DRAIN_TIMEOUT)
+        .await?
+        .wait_for_signal()
+        .await
+}
+
+/// serve in the background, in-flight requests get `drain_timeout` to finish after shutdown
+pub async fn spawn_server(
+    addr: SocketAddr,
+    state: AppState,
+    drain_timeout: Duration,
+) -> anyhow::Result<ServerHandle> {
+    DinoServer::builder()
+        .state(state)
+        .addr(addr)
+        .drain_timeout(drain_timeout)
+        .build()
+        .spawn()
+        .await
+}
 
-    let app = Router::new()
-        .route("/{*path}", any(handler))
-        .layer(ServerTimeLayer)
-        .with_state(state);
+impl AppState {
+    pub fn new(router: DashMap<String, Tenant>) -> Self {
+        Self {
+            routes: Arc::new(router),
+            max_versions: DEFAULT_MAX_VERSIONS,
+            evaluated: Arc::new(DashMap::new()),
+            started_at: Instant::now(),
+            rate_limiter: RateLimiter::new(),
+            proxy: ProxyClient::new(),
+        }
+    }
 
-    axum::serve(listener, app).await?;
+    pub fn with_max_versions(mut self, max_versions: usize) -> Self {
+        self.max_versions = max_versions;
+        self
+    }
 
-    Ok(())
+    /// an exact host wins over wildcards (the most specific first), the default tenant `*` comes last;
+    /// within a host the longest mount point wins, returned along with the tenant
+    pub fn find_tenant<'a>(&self, host: &str, path: &'a str) -> Option<(Tenant, &'a str)> {
+        tenant::host_patterns(host).iter().find_map(|pattern| {
+            tenant::mount_prefixes(path).find_map(|mount| {
+                let key = format!("{pattern}{mount}");
+                self.routes.get(&key).map(|t| (t.clone(), mount))
+            })
+        })
+    }
 }
 
-impl AppState {
-    pub fn new(router: DashMap<String, SwappableAppRouter>) -> Self {
-        Self { routes: router }
+impl From<Vec<TenentRouter>> for AppState {
+    /// aliases share the routers of their tenant, so a deploy applies to all of them
+    fn from(routers: Vec<TenentRouter>) -> Self {
+        let routes = DashMap::new();
+        for t in routers {
+            let tenant = Tenant::new(t.router);
+            tenant.set_canary(t.canary);
+            let mount = t.mount.as_deref();
+            for alias in t.aliases {
+                routes.insert(tenant::tenant_key(&alias, mount), tenant.clone());
+            }
+            routes.insert(tenant::tenant_key(&t.host, mount), tenant);
+        }
+
+        Self::new(routes)
     }
 }
 
@@ -62,13 +158,132 @@ impl TenentRouter {
         Self {
             host: host.into(),
             router,
+            canary: None,
+            aliases: Vec::new(),
+            mount: None,
         }
     }
+
+    /// serve the tenant under a path prefix like `/svc/orders`
+    pub fn with_mount(mut self, mount: impl Into<String>) -> Self {
+        self.mount = Some(mount.into());
+        self
+    }
+
+    pub fn with_aliases(mut self, aliases: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.aliases = aliases.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_canary(mut self, canary: Canary) -> Self {
+        self.canary = Some(canary);
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
 
     #[test]
     fn dino_server_should_work() {}
+
+    #[test]
+    fn app_state_find_tenant_should_work() -> anyhow::Result<()> {
+        let config: ProjectConfig = serde_yml::from_str(include_str!("../fixtures/config.yml"))?;
+        let router = |version: &str| {
+            SwappableAppRouter::try_new_with_version(version, "", config.clone()).unwrap()
+        };
+        let state = AppState::from(vec![
+            TenentRouter::new("api.example.com", router("api")).with_aliases(["API.example.org"]),
+            TenentRouter::new("*.example.com", router("wildcard")),
+            TenentRouter::new("*.eu.example.com", router("eu")),
+        ]);
+        let version = |host: &str| {
+            state
+                .find_tenant(host, "/")
+                .map(|(t, _)| t.primary.active_version())
+        };
+
+        assert_eq!(version("api.example.com").as_deref(), Some("api"));
+        assert_eq!(version("api.example.org").as_deref(), Some("api"));
+        assert_eq!(version("www.example.com").as_deref(), Some("wildcard"));
+        assert_eq!(version("a.b.example.com").as_deref(), Some("wildcard"));
+        assert_eq!(version("www.eu.example.com").as_deref(), Some("eu"));
+        assert_eq!(version("example.com"), None);
+        assert_eq!(version("unknown.org"), None);
+
+        state
+            .routes
+            .insert(DEFAULT_HOST.to_string(), Tenant::new(router("default")));
+        assert_eq!(version("unknown.org").as_deref(), Some("default"));
+        assert_eq!(version("www.example.com").as_deref(), Some("wildcard"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn app_state_find_mounted_tenant_should_work() -> anyhow::Result<()> {
+        let config: ProjectConfig = serde_yml::from_str(include_str!("../fixtures/config.yml"))?;
+        let router = |version: &str| {
+            SwappableAppRouter::try_new_with_version(version, "", config.clone()).unwrap()
+        };
+        let state = AppState::from(vec![
+            TenentRouter::new("internal", router("root")),
+            TenentRouter::new("internal", router("orders")).with_mount("/svc/orders/"),
+            TenentRouter::new("*", router("users")).with_mount("/svc/users"),
+        ]);
+        let find = |host: &str, path: &'static str| {
+            state
+                .find_tenant(host, path)
+                .map(|(t, mount)| (t.primary.active_version(), mount))
+        };
+
+        assert_eq!(
+            find("internal", "/svc/orders/1"),
+            Some(("orders".into(), "/svc/orders"))
+        );
+        assert_eq!(
+            find("internal", "/svc/orders"),
+            Some(("orders".into(), "/svc/orders"))
+        );
+        assert_eq!(find("internal", "/svc/ordersx"), Some(("root".into(), "")));
+        assert_eq!(find("internal", "/svc/users/1"), Some(("root".into(), "")));
+        assert_eq!(
+            find("other", "/svc/users/1"),
+            Some(("users".into(), "/svc/users"))
+        );
+        assert_eq!(find("other", "/"), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_handle_shutdown_should_work() -> anyhow::Result<()> {
+        let handle = spawn_server(
+            "127.0.0.1:0".parse()?,
+            AppState::new(DashMap::new()),
+            Duration::from_secs(1),
+        )
+        .await?;
+        let addr = handle.local_addr();
+
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: unknown\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await?;
+        assert!(res.starts_with("HTTP/1.1 404"));
+
+        handle.shutdown();
+        handle.wait().await?;
+        assert!(TcpStream::connect(addr).await.is_err());
+
+        Ok(())
+    }
 }
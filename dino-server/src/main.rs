@@ -1,5 +1,103 @@
+use clap::Parser;
+use dino_server::{
+    init_tracing, shutdown_signal, spawn_metrics_server, spawn_redirect_server, start_admin_server,
+    AccessLogLayer, AppState, DinoServer, ServerConfig,
+};
+use std::path::PathBuf;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+#[command(name = "dino-server", version, author, about, long_about = None)]
+struct Opts {
+    // server config with the listen address and tenants
+    #[arg(short, long, default_value = "server.yml")]
+    config: PathBuf,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    let opts = Opts::parse();
+    let config = ServerConfig::load(&opts.config)?;
+    // pending spans are exported when the guard is dropped on exit
+    let _tracing = init_tracing(config.otel.as_ref())?;
+    let routers = config.load_routers()?;
+
+    for tenant in config.tenants.iter() {
+        info!(
+            "Loaded tenant {} from {}",
+            tenant.host,
+            tenant.bundle.display()
+        );
+    }
+
+    let state = AppState::from(routers).with_max_versions(config.max_versions);
+    let shutdown = CancellationToken::new();
+    let access_log = config
+        .access_log
+        .as_ref()
+        .map(AccessLogLayer::new)
+        .transpose()?;
+    let server = |addr| {
+        DinoServer::builder()
+            .state(state.clone())
+            .addr(addr)
+            .drain_timeout(config.drain_timeout)
+            .access_log(access_log.clone())
+            .probes(config.probes.clone())
+            .shutdown(shutdown.clone())
+            .build()
+    };
+
+    let mut handles = Vec::new();
+    match config.tls.as_ref() {
+        Some(tls) => {
+            let certs = config.load_certs()?;
+            tokio::spawn(certs.clone().watch(tls.reload_interval, shutdown.clone()));
+            handles.push(server(tls.addr).spawn_tls(&certs).await?);
+
+            let http = if tls.redirect_http {
+                let port = tls.addr.port();
+                spawn_redirect_server(config.addr, port, config.drain_timeout, shutdown.clone())
+                    .await?
+            } else {
+                server(config.addr).spawn().await?
+            };
+            handles.push(http);
+        }
+        None => handles.push(server(config.addr).spawn().await?),
+    }
+
+    if let Some(metrics) = config.metrics.as_ref() {
+        handles.push(
+            spawn_metrics_server(metrics.addr, config.drain_timeout, shutdown.clone()).await?,
+        );
+    }
+
+    let admin = config.admin.map(|admin| {
+        tokio::spawn(start_admin_server(
+            admin.addr,
+            admin.token,
+            admin.max_body_size,
+            state.clone(),
+            shutdown.clone(),
+        ))
+    });
+
+    tokio::select! {
+        _ = shutdown_signal() => {
+            info!("Shutdown signal received, draining connections");
+            shutdown.cancel();
+        }
+        _ = shutdown.cancelled() => {}
+    }
+
+    for handle in handles {
+        handle.wait().await?;
+    }
+    if let Some(admin) = admin {
+        admin.await??;
+    }
+
     Ok(())
 }
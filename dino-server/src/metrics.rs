@@ -0,0 +1,160 @@
+use crate::server::spawn_router;
+use crate::ServerHandle;
+use axum::{http::header::CONTENT_TYPE, response::IntoResponse, routing::get, Router};
+use prometheus::{
+    exponential_buckets, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::{net::SocketAddr, sync::LazyLock, time::Duration};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// metrics of the server and the js engine, shared by all tenants
+pub struct Metrics {
+    registry: Registry,
+    /// requests by tenant, route pattern and status
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    /// js workers running a handler right now, workers are not pooled yet, so this is the
+    /// number of requests in js and there is no queue to report
+    pub js_workers_active: IntGauge,
+    /// time spent in a js handler by tenant and handler
+    pub js_execution_duration: HistogramVec,
+    /// quickjs heap used after a handler finished, by tenant
+    pub js_heap_bytes: HistogramVec,
+    /// bundle reloads by tenant and result (success or failure)
+    pub bundle_reloads: IntCounterVec,
+}
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("dino_http_requests_total", "HTTP requests served"),
+            &["tenant", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "dino_http_request_duration_seconds",
+                "HTTP request latency in seconds",
+            ),
+            &["tenant", "route", "status"],
+        )
+        .unwrap();
+        let js_workers_active = IntGauge::new(
+            "dino_js_workers_active",
+            "JS workers running a handler, one per request as workers are not pooled",
+        )
+        .unwrap();
+        let js_execution_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "dino_js_execution_duration_seconds",
+                "Time spent running JS handlers in seconds",
+            ),
+            &["tenant", "handler"],
+        )
+        .unwrap();
+        let js_heap_bytes = HistogramVec::new(
+            HistogramOpts::new("dino_js_heap_bytes", "QuickJS heap used by a request")
+                .buckets(exponential_buckets(64.0 * 1024.0, 2.0, 12).unwrap()),
+            &["tenant"],
+        )
+        .unwrap();
+        let bundle_reloads = IntCounterVec::new(
+            Opts::new("dino_bundle_reloads_total", "Bundle reloads and deploys"),
+            &["tenant", "result"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(js_workers_active.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(js_execution_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(js_heap_bytes.clone())).unwrap();
+        registry.register(Box::new(bundle_reloads.clone())).unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            js_workers_active,
+            js_execution_duration,
+            js_heap_bytes,
+            bundle_reloads,
+        }
+    }
+
+    pub fn record_reload(&self, tenant: &str, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.bundle_reloads
+            .with_label_values(&[tenant, result])
+            .inc();
+    }
+
+    /// all metrics in the prometheus text format
+    pub fn encode(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .unwrap_or_default()
+    }
+}
+
+/// serves `GET /metrics`, meant to listen on its own port
+pub fn metrics_router() -> Router {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+pub async fn spawn_metrics_server(
+    addr: SocketAddr,
+    drain_timeout: Duration,
+    shutdown: CancellationToken,
+) -> anyhow::Result<ServerHandle> {
+    let listener = TcpListener::bind(addr).await?;
+    spawn_router(listener, metrics_router(), drain_timeout, shutdown)
+}
+
+async fn get_metrics() -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics().encode(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::Request};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn metrics_router_should_work() -> anyhow::Result<()> {
+        metrics().record_reload("metrics-test", false);
+
+        let req = Request::builder().uri("/metrics").body(Body::empty())?;
+        let res = metrics_router().oneshot(req).await?;
+        assert_eq!(res.headers()[CONTENT_TYPE], prometheus::TEXT_FORMAT);
+
+        let body = res.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8(body.to_vec())?;
+        assert!(
+            body.contains(r#"dino_bundle_reloads_total{result="failure",tenant="metrics-test"} 1"#)
+        );
+
+        Ok(())
+    }
+}
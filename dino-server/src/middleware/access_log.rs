@@ -0,0 +1,333 @@
+use crate::{AccessLogConfig, AccessLogFormat, LogRotation, RequestId};
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, Request},
+    http::{header::CONTENT_LENGTH, Method},
+    response::Response,
+};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::{
+    future::Future,
+    io::Write,
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+use tracing::warn;
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
+
+/// the route which served a request, set on the response by the handler
+#[derive(Debug, Clone)]
+pub struct RouteInfo {
+    /// name of the project
+    pub tenant: String,
+    /// path pattern of the route, e.g. `/api/hello/{id}`
+    pub route: String,
+    pub handler: String,
+}
+
+/// writes one line per request, as json or in the common log format
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    format: AccessLogFormat,
+    writer: NonBlocking,
+    // pending lines are flushed when the last clone is dropped, keep one until shutdown
+    _guard: Arc<WorkerGuard>,
+}
+
+#[derive(Clone)]
+pub struct AccessLogMiddleware<S> {
+    inner: S,
+    // keeps the writer alive as long as a route may log
+    log: AccessLogLayer,
+}
+
+#[derive(Debug, Serialize)]
+struct AccessLogEntry {
+    time: String,
+    request_id: Option<String>,
+    /// ip of the peer, unknown without `ConnectInfo`
+    client: Option<String>,
+    tenant: Option<String>,
+    method: String,
+    path: String,
+    /// e.g. `HTTP/1.1`, `HTTP/2.0`
+    protocol: String,
+    route: Option<String>,
+    handler: Option<String>,
+    status: u16,
+    bytes: Option<u64>,
+    latency_us: u128,
+}
+
+impl AccessLogLayer {
+    pub fn new(config: &AccessLogConfig) -> anyhow::Result<Self> {
+        let (writer, guard) = match &config.path {
+            Some(path) => {
+                let dir = path.parent().unwrap_or(Path::new(""));
+                let prefix = path
+                    .file_name()
+                    .ok_or_else(|| anyhow::anyhow!("invalid access log path: {}", path.display()))?
+                    .to_string_lossy();
+                let mut builder = RollingFileAppender::builder()
+                    .rotation(config.rotation.into())
+                    .filename_prefix(prefix);
+                if let Some(max_files) = config.max_files {
+                    builder = builder.max_log_files(max_files);
+                }
+                let dir = if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir
+                };
+                tracing_appender::non_blocking(builder.build(dir)?)
+            }
+            None => tracing_appender::non_blocking(std::io::stdout()),
+        };
+
+        Ok(Self {
+            format: config.format,
+            writer,
+            _guard: Arc::new(guard),
+        })
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLogMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLogMiddleware {
+            inner,
+            log: self.clone(),
+        }
+    }
+}
+
+impl<S> Service<Request> for AccessLogMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let start = Instant::now();
+        let time = Local::now();
+        let method = request.method().clone();
+        let path = request
+            .uri()
+            .path_and_query()
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| request.uri().path().to_string());
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone());
+        let client = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip().to_string());
+        let protocol = format!("{:?}", request.version());
+
+        let format = self.log.format;
+        let mut writer = self.log.writer.clone();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let res: Response = future.await?;
+
+            let route = res.extensions().get::<RouteInfo>();
+            let bytes = res
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok()?.parse().ok())
+                .or_else(|| res.body().size_hint().exact());
+            let entry = AccessLogEntry {
+                time: time.to_rfc3339(),
+                request_id,
+                client,
+                tenant: route.map(|r| r.tenant.clone()),
+                method: method.to_string(),
+                path,
+                protocol,
+                route: route.map(|r| r.route.clone()),
+                handler: route.map(|r| r.handler.clone()),
+                status: res.status().as_u16(),
+                bytes,
+                latency_us: start.elapsed().as_micros(),
+            };
+
+            let line = entry.format(format, &time, &method);
+            if let Err(e) = writer.write_all(line.as_bytes()) {
+                warn!("Write access log failed: {}", e);
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+impl AccessLogEntry {
+    fn format(&self, format: AccessLogFormat, time: &DateTime<Local>, method: &Method) -> String {
+        match format {
+            AccessLogFormat::Json => {
+                let mut line = serde_json::to_string(self).unwrap_or_default();
+                line.push('\n');
+                line
+            }
+            // host ident authuser [date] "request" status bytes
+            AccessLogFormat::Clf => format!(
+                "{} - - [{}] \"{} {} {}\" {} {}\n",
+                self.client.as_deref().unwrap_or("-"),
+                time.format("%d/%b/%Y:%H:%M:%S %z"),
+                method,
+                self.path,
+                self.protocol,
+                self.status,
+                self.bytes
+                    .map(|b| b.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            ),
+        }
+    }
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Never => Rotation::NEVER,
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RequestIdLayer;
+    use axum::{body::Body, routing::get, Router};
+    use std::fs;
+    use tower::ServiceExt;
+
+    async fn hello() -> Response {
+        let mut res = Response::new(Body::from("hello"));
+        res.extensions_mut().insert(RouteInfo {
+            tenant: "dino-test".to_string(),
+            route: "/api/{name}".to_string(),
+            handler: "hello".to_string(),
+        });
+        res
+    }
+
+    fn config(format: AccessLogFormat, dir: &tempfile::TempDir) -> AccessLogConfig {
+        AccessLogConfig {
+            format,
+            path: Some(dir.path().join("access.log")),
+            rotation: LogRotation::Never,
+            max_files: None,
+        }
+    }
+
+    async fn access_log(format: AccessLogFormat) -> anyhow::Result<String> {
+        let dir = tempfile::tempdir()?;
+        let layer = AccessLogLayer::new(&config(format, &dir))?;
+        let app = Router::new()
+            .route("/api/{name}", get(hello))
+            .layer(layer.clone())
+            .layer(RequestIdLayer);
+        let mut req = Request::builder()
+            .uri("/api/dino?a=1")
+            .version(axum::http::Version::HTTP_2)
+            .header("x-request-id", "abc")
+            .body(Body::empty())?;
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+        app.oneshot(req).await?;
+
+        // dropping the last layer flushes the log
+        drop(layer);
+        Ok(fs::read_to_string(dir.path().join("access.log"))?)
+    }
+
+    #[tokio::test]
+    async fn access_log_layer_json_should_work() -> anyhow::Result<()> {
+        let log = access_log(AccessLogFormat::Json).await?;
+        let entry: serde_json::Value = serde_json::from_str(log.trim())?;
+
+        assert_eq!(entry["request_id"], "abc");
+        assert_eq!(entry["client"], "10.0.0.1");
+        assert_eq!(entry["protocol"], "HTTP/2.0");
+        assert_eq!(entry["tenant"], "dino-test");
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["path"], "/api/dino?a=1");
+        assert_eq!(entry["route"], "/api/{name}");
+        assert_eq!(entry["handler"], "hello");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["bytes"], 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn access_log_layer_clf_should_work() -> anyhow::Result<()> {
+        let log = access_log(AccessLogFormat::Clf).await?;
+
+        assert!(log.starts_with("10.0.0.1 - - ["));
+        assert!(log.ends_with("] \"GET /api/dino?a=1 HTTP/2.0\" 200 5\n"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn access_log_should_log_route_of_errors() -> anyhow::Result<()> {
+        use crate::{DinoServer, ProjectConfig, SwappableAppRouter, TenentRouter};
+
+        let code = r#"(function(){
+            return { fail: async (req) => { throw new Error("boom"); } };
+        })()"#;
+        let project = "name: failing\nroutes:\n  /fail:\n    - method: GET\n      handler: fail\n";
+        let project: ProjectConfig = serde_yml::from_str(project)?;
+        let router = SwappableAppRouter::try_new(code, project)?;
+
+        let dir = tempfile::tempdir()?;
+        let layer = AccessLogLayer::new(&config(AccessLogFormat::Json, &dir))?;
+        let app = DinoServer::builder()
+            .state(vec![TenentRouter::new("localhost", router)])
+            .access_log(Some(layer.clone()))
+            .build()
+            .router();
+        let req = Request::builder()
+            .uri("/fail")
+            .header("host", "localhost")
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert!(res.status().is_server_error());
+
+        // dropping the last layer flushes the log
+        drop(layer);
+        let log = fs::read_to_string(dir.path().join("access.log"))?;
+        let entry: serde_json::Value = serde_json::from_str(log.trim())?;
+        assert_eq!(entry["tenant"], "failing");
+        assert_eq!(entry["route"], "/fail");
+        assert_eq!(entry["handler"], "fail");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use crate::CompressionConfig;
+use axum::{
+    body::HttpBody,
+    http::{header::CONTENT_TYPE, Response},
+};
+use tower_http::compression::{
+    predicate::{NotForContentType, SizeAbove},
+    CompressionLayer, DefaultPredicate, Predicate,
+};
+
+/// compresses by the rules of the project, set on the response by the handler
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionPredicate;
+
+/// gzip, brotli or zstd, by the `accept-encoding` of the client
+pub fn compression_layer() -> CompressionLayer<CompressionPredicate> {
+    CompressionLayer::new().compress_when(CompressionPredicate)
+}
+
+impl Predicate for CompressionPredicate {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: HttpBody,
+    {
+        let Some(config) = response.extensions().get::<CompressionConfig>() else {
+            return DefaultPredicate::new().should_compress(response);
+        };
+
+        if !SizeAbove::new(config.min_size).should_compress(response) {
+            return false;
+        }
+        // streamed events must reach the client as soon as they are sent
+        if !NotForContentType::SSE.should_compress(response) {
+            return false;
+        }
+        if config.content_types.is_empty() {
+            return NotForContentType::IMAGES.should_compress(response);
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        config
+            .content_types
+            .iter()
+            .any(|prefix| content_type.starts_with(prefix.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    fn response(content_type: &str, body: impl Into<Body>) -> Response<Body> {
+        Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(body.into())
+            .unwrap()
+    }
+
+    #[test]
+    fn compression_predicate_should_work() {
+        let body = "dino".repeat(16);
+
+        // the default rules of tower-http without a project config
+        assert!(CompressionPredicate.should_compress(&response("text/plain", body.clone())));
+        assert!(!CompressionPredicate.should_compress(&response("text/plain", "dino")));
+
+        let config = CompressionConfig {
+            min_size: 128,
+            content_types: vec!["application/json".to_string()],
+        };
+        let mut res = response("application/json", body.clone());
+        res.extensions_mut().insert(config.clone());
+        assert!(!CompressionPredicate.should_compress(&res));
+
+        let body = body.repeat(4);
+        let mut res = response("application/json; charset=utf-8", body.clone());
+        res.extensions_mut().insert(config.clone());
+        assert!(CompressionPredicate.should_compress(&res));
+
+        let mut res = response("text/html", body);
+        res.extensions_mut().insert(config);
+        assert!(!CompressionPredicate.should_compress(&res));
+    }
+}
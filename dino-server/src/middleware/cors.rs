@@ -0,0 +1,349 @@
+use crate::{AppState, CorsConfig};
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{
+            ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+            ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, HOST, ORIGIN, VARY,
+        },
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    response::Response,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+/// applies the cors policy of the matched route, preflight requests are answered without
+/// calling the handler, even if the route has no `OPTIONS` handler
+#[derive(Clone)]
+pub struct CorsLayer {
+    state: AppState,
+}
+
+#[derive(Clone)]
+pub struct CorsMiddleware<S> {
+    inner: S,
+    state: AppState,
+}
+
+impl CorsLayer {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+}
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = CorsMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CorsMiddleware {
+            inner,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S> Service<Request> for CorsMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        // same-origin requests do not send an origin
+        let Some(origin) = request.headers().get(ORIGIN).cloned() else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        let requested_method = request
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|v| Method::from_bytes(v.as_bytes()).ok());
+        match requested_method {
+            Some(method) if request.method() == Method::OPTIONS => {
+                // routes which do not exist for the method are left to the handler
+                if let Some((cors, true)) = self.policy(&mut request, &method) {
+                    let res = preflight(&cors, &origin, &method, request.headers());
+                    return Box::pin(async move { Ok(res) });
+                }
+                Box::pin(self.inner.call(request))
+            }
+            _ => {
+                let method = request.method().clone();
+                let policy = self.policy(&mut request, &method);
+                let future = self.inner.call(request);
+                Box::pin(async move {
+                    let mut res: Response = future.await?;
+                    if let Some((cors, _)) = policy {
+                        allow_origin(&cors, &origin, res.headers_mut());
+                    }
+
+                    Ok(res)
+                })
+            }
+        }
+    }
+}
+
+impl<S> CorsMiddleware<S> {
+    /// the policy of the variant serving the request, and if the route was matched;
+    /// the variant is kept in the request, so the handler serves it with the same one
+    fn policy(&self, request: &mut Request, method: &Method) -> Option<(CorsConfig, bool)> {
+        let host = request
+            .headers()
+            .get(HOST)
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| request.uri().host())?;
+        let host = host.split(':').next().unwrap_or(host);
+        let (tenant, mount) = self.state.find_tenant(host, request.uri().path())?;
+
+        let selected = tenant.select(request.headers());
+        let router = selected.router.clone();
+        let path = match &request.uri().path()[mount.len()..] {
+            "" => "/",
+            path => path,
+        };
+        let path = router.rewrite(path).into_owned();
+        request.extensions_mut().insert(selected);
+
+        let route = router.match_it(method.clone(), &path).ok().map(|m| m.value);
+        let cors = router.config.cors(route)?.clone();
+
+        Some((cors, route.is_some()))
+    }
+}
+
+/// an answer without cors headers if the origin or the method is not allowed
+fn preflight(
+    cors: &CorsConfig,
+    origin: &HeaderValue,
+    method: &Method,
+    req: &HeaderMap,
+) -> Response {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = StatusCode::NO_CONTENT;
+    let headers = res.headers_mut();
+    for name in [
+        ORIGIN,
+        ACCESS_CONTROL_REQUEST_METHOD,
+        ACCESS_CONTROL_REQUEST_HEADERS,
+    ] {
+        headers.append(VARY, name.into());
+    }
+
+    let method_allowed = cors.methods.is_empty()
+        || cors
+            .methods
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method.as_str()));
+    if !method_allowed || !allow_origin(cors, origin, headers) {
+        return res;
+    }
+
+    let methods = if cors.methods.is_empty() {
+        method.to_string()
+    } else {
+        cors.methods.join(", ")
+    };
+    if let Ok(methods) = HeaderValue::from_str(&methods) {
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+    }
+
+    let allow_headers = if cors.headers.iter().any(|h| h == "*") {
+        req.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned()
+    } else if cors.headers.is_empty() {
+        None
+    } else {
+        HeaderValue::from_str(&cors.headers.join(", ")).ok()
+    };
+    if let Some(allow_headers) = allow_headers {
+        headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+    }
+
+    if let Some(max_age) = cors.max_age {
+        headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+    }
+
+    res
+}
+
+/// true if the origin is allowed, `*` never allows credentials
+fn allow_origin(cors: &CorsConfig, origin: &HeaderValue, headers: &mut HeaderMap) -> bool {
+    let any = !cors.credentials && cors.origins.iter().any(|o| o == "*");
+    let allowed = any
+        || cors
+            .origins
+            .iter()
+            .any(|o| o.as_bytes() == origin.as_bytes());
+    if !allowed {
+        return false;
+    }
+
+    if any {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    } else {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        headers.append(VARY, ORIGIN.into());
+    }
+    if cors.credentials {
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Canary, DinoServer, ProjectConfig, SwappableAppRouter, TenentRouter};
+    use axum::Router;
+    use tower::ServiceExt;
+
+    const CODE: &str = r#"(function(){
+        return { hello: async (req) => ({ status: 200, headers: {}, body: "hello" }) };
+    })()"#;
+
+    const CONFIG: &str = r#"
+    name: cors
+    cors:
+      origins: ["https://app.example.com"]
+      headers: ["*"]
+      max_age: 1h
+    routes:
+      /api/hello:
+        - method: GET
+          handler: hello
+        - method: POST
+          handler: hello
+      /api/public:
+        - method: GET
+          handler: hello
+          cors:
+            origins: ["*"]
+    "#;
+
+    fn app() -> Router {
+        let config: ProjectConfig = serde_yml::from_str(CONFIG).unwrap();
+        let router = SwappableAppRouter::try_new(CODE, config).unwrap();
+        DinoServer::new(vec![TenentRouter::new("localhost", router)]).router()
+    }
+
+    fn request(method: Method, uri: &str, origin: &str) -> Request {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(HOST, "localhost")
+            .header(ORIGIN, origin)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_should_work() -> anyhow::Result<()> {
+        let mut req = request(Method::OPTIONS, "/api/hello", "https://app.example.com");
+        req.headers_mut()
+            .insert(ACCESS_CONTROL_REQUEST_METHOD, "POST".parse()?);
+        req.headers_mut()
+            .insert(ACCESS_CONTROL_REQUEST_HEADERS, "x-token".parse()?);
+        let res = app().oneshot(req).await?;
+
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let headers = res.headers();
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "POST");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_HEADERS], "x-token");
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "3600");
+
+        let mut req = request(Method::OPTIONS, "/api/hello", "https://evil.com");
+        req.headers_mut()
+            .insert(ACCESS_CONTROL_REQUEST_METHOD, "POST".parse()?);
+        let res = app().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(!res.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cors_request_should_work() -> anyhow::Result<()> {
+        let req = request(Method::GET, "/api/hello", "https://app.example.com");
+        let res = app().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert!(!res.headers().contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+
+        let req = request(Method::GET, "/api/hello", "https://evil.com");
+        let res = app().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        // the route replaces the policy of the project
+        let req = request(Method::GET, "/api/public", "https://evil.com");
+        let res = app().oneshot(req).await?;
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!res.headers().contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+
+        Ok(())
+    }
+
+    #[test]
+    fn cors_should_reject_any_origin_with_credentials() {
+        let config = CONFIG.replace(
+            "origins: [\"*\"]",
+            "origins: [\"*\"]\n            credentials: true",
+        );
+        let config: ProjectConfig = serde_yml::from_str(&config).unwrap();
+        assert!(SwappableAppRouter::try_new(CODE, config).is_err());
+    }
+
+    #[tokio::test]
+    async fn cors_should_use_policy_of_canary() -> anyhow::Result<()> {
+        let primary: ProjectConfig = serde_yml::from_str(
+            "name: primary\nroutes:\n  /api/hello:\n    - method: GET\n      handler: hello",
+        )?;
+        let canary: ProjectConfig = serde_yml::from_str(CONFIG)?;
+        let tenant = TenentRouter::new("localhost", SwappableAppRouter::try_new(CODE, primary)?)
+            .with_mount("/svc")
+            .with_canary(Canary {
+                router: SwappableAppRouter::try_new(CODE, canary)?,
+                weight: 100,
+                sticky_header: None,
+            });
+        let app = DinoServer::new(vec![tenant]).router();
+
+        let req = request(Method::GET, "/svc/api/hello", "https://app.example.com");
+        let res = app.oneshot(req).await?;
+        assert_eq!(
+            res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        // the variant is kept for the mount point only
+        let cookie = res.headers()[axum::http::header::SET_COOKIE].to_str()?;
+        assert_eq!(cookie, "dino-variant=canary; Path=/svc; HttpOnly");
+
+        Ok(())
+    }
+}
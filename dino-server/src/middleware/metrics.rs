@@ -0,0 +1,65 @@
+use crate::{metrics, RouteInfo};
+use axum::{extract::Request, response::Response};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+/// counts requests and observes their latency by tenant, route pattern and status
+#[derive(Clone)]
+pub struct MetricsLayer;
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsMiddleware { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsMiddleware<S> {
+    inner: S,
+}
+
+impl<S> Service<Request> for MetricsMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let start = Instant::now();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let res: Response = future.await?;
+
+            // requests not served by a route are grouped, to keep the label cardinality low
+            let (tenant, route) = match res.extensions().get::<RouteInfo>() {
+                Some(info) => (info.tenant.as_str(), info.route.as_str()),
+                None => ("", ""),
+            };
+            let status = res.status();
+            let labels = [tenant, route, status.as_str()];
+            let metrics = metrics();
+            metrics.http_requests.with_label_values(&labels).inc();
+            metrics
+                .http_request_duration
+                .with_label_values(&labels)
+                .observe(start.elapsed().as_secs_f64());
+
+            Ok(res)
+        })
+    }
+}
@@ -1,6 +1,16 @@
+mod access_log;
+mod compression;
+mod cors;
+mod metrics;
+mod request_id;
 mod server_time;
 
+pub use access_log::{AccessLogLayer, RouteInfo};
+pub use compression::{compression_layer, CompressionPredicate};
+pub use cors::CorsLayer;
+pub use metrics::MetricsLayer;
+pub use request_id::{RequestId, RequestIdLayer};
 pub use server_time::ServerTimeLayer;
 
 const SERVER_TIME_HEADER: &str = "x-server-time";
-const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
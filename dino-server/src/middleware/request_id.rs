@@ -0,0 +1,129 @@
+use super::REQUEST_ID_HEADER;
+use crate::trace::set_parent;
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderValue},
+    response::Response,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+use tracing::{info_span, Instrument};
+use uuid::Uuid;
+
+/// longer ids sent by clients are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// id of the request, available in the request extensions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+#[derive(Clone)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdMiddleware { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestIdMiddleware<S> {
+    inner: S,
+}
+
+impl<S> Service<Request> for RequestIdMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        // keep the id of the client, so the request can be traced across services
+        let id = match get_request_id(request.headers()) {
+            Some(id) => id,
+            None => {
+                let id = Uuid::now_v7().to_string();
+                let value = HeaderValue::from_str(&id).expect("uuid is a valid header value");
+                request.headers_mut().insert(REQUEST_ID_HEADER, value);
+                id
+            }
+        };
+        request.extensions_mut().insert(RequestId(id.clone()));
+
+        let span = info_span!(
+            "request",
+            request_id = %id,
+            method = %request.method(),
+            uri = %request.uri(),
+        );
+        set_parent(&span, request.headers());
+        let future = self.inner.call(request);
+        Box::pin(
+            async move {
+                let mut res: Response = future.await?;
+                if let Ok(value) = HeaderValue::from_str(&id) {
+                    res.headers_mut().insert(REQUEST_ID_HEADER, value);
+                }
+
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+fn get_request_id(headers: &HeaderMap) -> Option<String> {
+    let id = headers.get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_graphic());
+
+    valid.then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    async fn echo(Extension(id): Extension<RequestId>) -> String {
+        id.0
+    }
+
+    #[tokio::test]
+    async fn request_id_layer_should_work() -> anyhow::Result<()> {
+        let app = Router::new().route("/", get(echo)).layer(RequestIdLayer);
+
+        let req = Request::builder()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.headers()[REQUEST_ID_HEADER], "abc-123");
+
+        let req = Request::builder()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "has space")
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        let id = res.headers()[REQUEST_ID_HEADER].to_str()?;
+        assert_eq!(Uuid::parse_str(id)?.get_version_num(), 7);
+
+        Ok(())
+    }
+}
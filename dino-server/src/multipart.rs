@@ -0,0 +1,118 @@
+use crate::{AppError, FormPart, MultipartConfig};
+use axum::body::Body;
+use multer::{Constraints, Multipart, SizeLimit};
+
+/// return the boundary if the content type is `multipart/form-data`
+pub(crate) fn get_boundary(content_type: &str) -> Option<String> {
+    let mime: mime::Mime = content_type.parse().ok()?;
+    if mime.type_() != mime::MULTIPART || mime.subtype() != mime::FORM_DATA {
+        return None;
+    }
+
+    multer::parse_boundary(content_type).ok()
+}
+
+/// read all fields and files of a multipart body with the limits of the route,
+/// `max_body_size` is used if the route doesn't limit the whole stream
+pub(crate) async fn parse_multipart(
+    body: Body,
+    boundary: String,
+    config: &MultipartConfig,
+    max_body_size: usize,
+) -> Result<Vec<FormPart>, AppError> {
+    let mut limit = SizeLimit::new().whole_stream(config.max_size.unwrap_or(max_body_size as u64));
+    if let Some(size) = config.max_field_size {
+        limit = limit.per_field(size);
+    }
+
+    let constraints = Constraints::new().size_limit(limit);
+    let mut multipart = Multipart::with_constraints(body.into_data_stream(), boundary, constraints);
+
+    let mut parts = Vec::new();
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        let filename = field.file_name().map(|s| s.to_string());
+        let content_type = field.content_type().map(|m| m.to_string());
+        let data = field.bytes().await?.to_vec();
+
+        // plain fields are exposed as text as well
+        let value = match filename {
+            Some(_) => None,
+            None => String::from_utf8(data.clone()).ok(),
+        };
+
+        let part = FormPart::builder()
+            .name(name)
+            .filename(filename)
+            .content_type(content_type)
+            .value(value)
+            .data(data)
+            .build();
+        parts.push(part);
+    }
+
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "--X-BOUNDARY\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        hello\r\n\
+        --X-BOUNDARY\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        abcdef\r\n\
+        --X-BOUNDARY--\r\n";
+
+    #[test]
+    fn get_boundary_should_work() {
+        let boundary = get_boundary("multipart/form-data; boundary=X-BOUNDARY");
+        assert_eq!(boundary.as_deref(), Some("X-BOUNDARY"));
+
+        assert!(get_boundary("application/json").is_none());
+        assert!(get_boundary("multipart/mixed; boundary=X-BOUNDARY").is_none());
+    }
+
+    #[tokio::test]
+    async fn parse_multipart_should_work() {
+        let config = MultipartConfig::default();
+        let parts = parse_multipart(Body::from(BODY), "X-BOUNDARY".to_string(), &config, 1024)
+            .await
+            .unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "title");
+        assert_eq!(parts[0].value.as_deref(), Some("hello"));
+        assert_eq!(parts[1].filename.as_deref(), Some("a.txt"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("text/plain"));
+        assert!(parts[1].value.is_none());
+        assert_eq!(parts[1].data.0, b"abcdef");
+    }
+
+    #[tokio::test]
+    async fn parse_multipart_should_respect_limits() {
+        let config = MultipartConfig {
+            max_size: None,
+            max_field_size: Some(4),
+        };
+        let ret = parse_multipart(Body::from(BODY), "X-BOUNDARY".to_string(), &config, 1024).await;
+
+        assert!(matches!(
+            ret,
+            Err(AppError::Multipart(multer::Error::FieldSizeExceeded { .. }))
+        ));
+
+        let config = MultipartConfig::default();
+        let ret = parse_multipart(Body::from(BODY), "X-BOUNDARY".to_string(), &config, 16).await;
+
+        assert!(matches!(
+            ret,
+            Err(AppError::Multipart(
+                multer::Error::StreamSizeExceeded { .. }
+            ))
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{router::calc_version, AppState, JsWorker, ProbeConfig, TenantInfo};
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::task::spawn_blocking;
+use tracing::warn;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerInfo {
+    /// version of dino-server
+    pub version: String,
+    pub uptime_secs: u64,
+    pub tenants: Vec<TenantInfo>,
+}
+
+/// health, readiness and info endpoints, served on every host before the tenants
+pub fn probe_router(config: &ProbeConfig) -> Router<AppState> {
+    Router::new()
+        .route(&config.health, get(health))
+        .route(&config.ready, get(ready))
+        .route(&config.info, get(info))
+}
+
+impl AppState {
+    /// true if the active bundles of all tenants evaluate in a js worker,
+    /// bundles are evaluated once, when they become active
+    pub async fn check_ready(&self) -> bool {
+        // aliases and mounts share routers, evaluate every bundle once
+        let mut active = HashMap::new();
+        for tenant in self.routes.iter() {
+            let mut routers = vec![tenant.primary.load()];
+            if let Some(canary) = tenant.canary() {
+                routers.push(canary.router.load());
+            }
+            for router in routers {
+                active
+                    .entry(calc_version(&router.code))
+                    .or_insert_with(|| (router.version.clone(), router.code.clone()));
+            }
+        }
+        self.evaluated.retain(|hash, _| active.contains_key(hash));
+
+        let pending = active
+            .into_iter()
+            .filter(|(hash, _)| !self.evaluated.contains_key(hash))
+            .collect::<Vec<_>>();
+        let results = spawn_blocking(move || {
+            pending
+                .into_iter()
+                .map(|(hash, (version, code))| match JsWorker::try_new(&code) {
+                    Ok(_) => (hash, true),
+                    Err(e) => {
+                        warn!("Evaluate bundle {} failed: {}", version, e);
+                        (hash, false)
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        for (hash, ok) in results {
+            self.evaluated.insert(hash, ok);
+        }
+
+        self.evaluated.iter().all(|r| *r.value())
+    }
+}
+
+async fn health() -> &'static str {
+    "ok"
+}
+
+async fn ready(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    if state.check_ready().await {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    }
+}
+
+async fn info(State(state): State<AppState>) -> Json<ServerInfo> {
+    let mut tenants = state
+        .routes
+        .iter()
+        .map(|r| TenantInfo::new(r.key(), r.value()))
+        .collect::<Vec<_>>();
+    tenants.sort_by(|a, b| a.host.cmp(&b.host));
+
+    Json(ServerInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: state.started_at.elapsed().as_secs(),
+        tenants,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DinoServer, ProjectConfig, SwappableAppRouter, TenentRouter};
+    use axum::{body::Body, extract::Request};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    const CODE: &str = r#"(function(){
+        return { hello: async (req) => ({ status: 200, headers: {}, body: "hello" }) };
+    })()"#;
+
+    fn config() -> ProjectConfig {
+        serde_yml::from_str(include_str!("../fixtures/config.yml")).unwrap()
+    }
+
+    fn app(code: &str) -> Router {
+        let router = SwappableAppRouter::try_new_with_version("v1", code, config()).unwrap();
+        // the default tenant would serve the probe paths otherwise
+        DinoServer::new(vec![TenentRouter::new("*", router)]).router()
+    }
+
+    async fn get(app: &Router, uri: &str) -> anyhow::Result<(StatusCode, String)> {
+        let req = Request::builder()
+            .uri(uri)
+            .header("host", "localhost")
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        let status = res.status();
+        let body = res.into_body().collect().await?.to_bytes();
+        Ok((status, String::from_utf8(body.to_vec())?))
+    }
+
+    #[tokio::test]
+    async fn probes_should_work() -> anyhow::Result<()> {
+        let app = app(CODE);
+
+        assert_eq!(get(&app, "/healthz").await?, (StatusCode::OK, "ok".into()));
+        assert_eq!(
+            get(&app, "/readyz").await?,
+            (StatusCode::OK, "ready".into())
+        );
+
+        let (status, body) = get(&app, "/_dino/info").await?;
+        assert_eq!(status, StatusCode::OK);
+        let info: ServerInfo = serde_json::from_str(&body)?;
+        assert_eq!(info.tenants.len(), 1);
+        assert_eq!(info.tenants[0].host, "*");
+        assert_eq!(info.tenants[0].active, "v1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn readyz_should_fail_on_invalid_bundle() -> anyhow::Result<()> {
+        let app = app("(function(){");
+
+        assert_eq!(get(&app, "/healthz").await?.0, StatusCode::OK);
+        assert_eq!(
+            get(&app, "/readyz").await?.0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn readyz_should_follow_active_versions() -> anyhow::Result<()> {
+        let router = SwappableAppRouter::try_new_with_version("v1", CODE, config())?;
+        let app = DinoServer::new(vec![TenentRouter::new("*", router.clone())]).router();
+        assert_eq!(get(&app, "/readyz").await?.0, StatusCode::OK);
+
+        router.deploy("v2", "(function(){", config())?;
+        assert_eq!(
+            get(&app, "/readyz").await?.0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        router.rollback()?;
+        assert_eq!(get(&app, "/readyz").await?.0, StatusCode::OK);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+use crate::{error::AppError, router::fill_params, traceparent, ProxyConfig, TRACEPARENT_HEADER};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    http::{
+        header::{CONNECTION, CONTENT_LENGTH, HOST},
+        request::Parts,
+        HeaderMap, HeaderName, HeaderValue, Uri,
+    },
+    response::Response,
+};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::{LengthLimitError, Limited};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{sleep, timeout, Sleep};
+use tracing::warn;
+
+/// headers of a single connection, never forwarded
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// pooled connections to the upstreams of all tenants, shared by all clones
+#[derive(Debug, Clone)]
+pub struct ProxyClient {
+    client: Client<HttpConnector, Body>,
+}
+
+impl ProxyClient {
+    pub fn new() -> Self {
+        let client = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        Self { client }
+    }
+
+    /// forward the request to the upstream of the route, the bodies are streamed both ways
+    ///
+    /// the body of the client must be read within `read_timeout`, the response of the
+    /// upstream is only bounded by the timeout of the proxy
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn forward(
+        &self,
+        config: &ProxyConfig,
+        params: &HashMap<String, String>,
+        path: &str,
+        parts: &Parts,
+        body: Body,
+        max_body_size: usize,
+        read_timeout: Duration,
+    ) -> Result<Response, AppError> {
+        let content_length = parts
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if content_length.is_some_and(|len| len > max_body_size) {
+            return Err(AppError::PayloadTooLarge(max_body_size));
+        }
+
+        let uri = upstream_uri(config, params, path, parts.uri.query())?;
+        let body = Limited::new(body, max_body_size);
+        let mut req = Request::new(Body::new(Deadline::new(body, read_timeout)));
+        *req.method_mut() = parts.method.clone();
+        *req.uri_mut() = uri;
+        *req.headers_mut() = forwarded_headers(parts);
+        rewrite_headers(req.headers_mut(), &config.headers);
+
+        let res = match timeout(config.timeout, self.client.request(req)).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(e)) => {
+                warn!("proxy to {} failed: {:?}", config.url, e);
+                let mut source = e.source();
+                while let Some(e) = source {
+                    if e.is::<LengthLimitError>() {
+                        return Err(AppError::PayloadTooLarge(max_body_size));
+                    }
+                    if e.is::<ReadTimeout>() {
+                        return Err(AppError::RequestTimeout(read_timeout));
+                    }
+                    source = e.source();
+                }
+                return Err(AppError::BadGateway(e.to_string()));
+            }
+            Err(_) => return Err(AppError::GatewayTimeout(config.timeout)),
+        };
+
+        let mut res = res.map(Body::new);
+        remove_hop_by_hop(res.headers_mut());
+        rewrite_headers(res.headers_mut(), &config.response_headers);
+
+        Ok(res)
+    }
+}
+
+impl Default for ProxyClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// the body of the client failed to arrive before the deadline
+#[derive(Debug)]
+struct ReadTimeout;
+
+impl fmt::Display for ReadTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request body read timeout")
+    }
+}
+
+impl Error for ReadTimeout {}
+
+/// a body failing with `ReadTimeout` once it is not read in time
+struct Deadline<B> {
+    body: B,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl<B> Deadline<B> {
+    fn new(body: B, timeout: Duration) -> Self {
+        let sleep = Box::pin(sleep(timeout));
+        Self { body, sleep }
+    }
+}
+
+impl<B> HttpBody for Deadline<B>
+where
+    B: HttpBody + Unpin,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Data = B::Data;
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Poll::Ready(frame) = Pin::new(&mut self.body).poll_frame(cx) {
+            return Poll::Ready(frame.map(|f| f.map_err(Into::into)));
+        }
+        match self.sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Some(Err(Box::new(ReadTimeout)))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// params of the route are substituted, a url without a path gets the path of the request
+fn upstream_uri(
+    config: &ProxyConfig,
+    params: &HashMap<String, String>,
+    path: &str,
+    query: Option<&str>,
+) -> Result<Uri, AppError> {
+    let invalid = |url: &str, e: &dyn fmt::Display| {
+        AppError::BadGateway(format!("invalid upstream url {url}: {e}"))
+    };
+    let params = params.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+    let url = fill_params(&config.url, params);
+    let uri: Uri = url.parse().map_err(|e| invalid(&url, &e))?;
+
+    // `http://legacy` and `http://legacy?v=2` have no path, `http://legacy/` has one
+    let authority = url
+        .trim_start_matches("http://")
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default();
+    let path = if authority.contains('/') {
+        uri.path()
+    } else {
+        path
+    };
+    let mut path_and_query = path.to_string();
+    for query in [uri.query(), query].into_iter().flatten() {
+        path_and_query.push(if path_and_query.contains('?') {
+            '&'
+        } else {
+            '?'
+        });
+        path_and_query.push_str(query);
+    }
+
+    let mut parts = uri.into_parts();
+    parts.path_and_query = Some(path_and_query.parse().map_err(|e| invalid(&url, &e))?);
+    Uri::from_parts(parts).map_err(|e| invalid(&url, &e))
+}
+
+/// the headers of the client, the host is the one of the upstream
+fn forwarded_headers(parts: &Parts) -> HeaderMap {
+    let mut headers = parts.headers.clone();
+    remove_hop_by_hop(&mut headers);
+
+    if let Some(host) = headers.remove(HOST) {
+        headers.insert("x-forwarded-host", host);
+    }
+    if let Some(ConnectInfo(addr)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        let forwarded_for = match headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+            Some(prev) => format!("{prev}, {}", addr.ip()),
+            None => addr.ip().to_string(),
+        };
+        if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+            headers.insert("x-forwarded-for", value);
+        }
+    }
+    if let Some(value) = traceparent(&parts.headers).and_then(|v| HeaderValue::from_str(&v).ok()) {
+        headers.insert(TRACEPARENT_HEADER, value);
+    }
+
+    headers
+}
+
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    // the connection header may name more headers of the connection
+    let named = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+    for name in named {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(name);
+    }
+}
+
+/// an empty value removes the header
+fn rewrite_headers(headers: &mut HeaderMap, rewrites: &indexmap::IndexMap<String, String>) {
+    for (name, value) in rewrites {
+        let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+            continue;
+        };
+        if value.is_empty() {
+            headers.remove(name);
+        } else if let Ok(value) = HeaderValue::from_str(value) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DinoServer, ProjectConfig, SwappableAppRouter, TenentRouter};
+    use axum::{http::StatusCode, routing::any, Router};
+    use http_body_util::BodyExt;
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    fn config(url: &str) -> ProxyConfig {
+        serde_yml::from_str(&format!("url: {url}")).unwrap()
+    }
+
+    #[test]
+    fn upstream_uri_should_work() {
+        let params = HashMap::from([("id".to_string(), "1".to_string())]);
+        let uri = upstream_uri(
+            &config("http://legacy/items/{id}"),
+            &params,
+            "/a",
+            Some("x=1"),
+        );
+        assert_eq!(uri.unwrap(), "http://legacy/items/1?x=1");
+
+        let uri = upstream_uri(&config("http://legacy:8080"), &params, "/api/1", None);
+        assert_eq!(uri.unwrap(), "http://legacy:8080/api/1");
+
+        let uri = upstream_uri(
+            &config("http://legacy/?v=2"),
+            &params,
+            "/api/1",
+            Some("x=1"),
+        );
+        assert_eq!(uri.unwrap(), "http://legacy/?v=2&x=1");
+
+        // the query of the upstream is not its path
+        let uri = upstream_uri(&config("http://legacy?v=2"), &params, "/api/1", Some("x=1"));
+        assert_eq!(uri.unwrap(), "http://legacy/api/1?v=2&x=1");
+    }
+
+    /// echoes the request as `{method} {uri} {x-token} {x-forwarded-host} {body}`
+    async fn upstream() -> SocketAddr {
+        let app = Router::new().fallback(any(|req: Request| async move {
+            let (parts, body) = req.into_parts();
+            if parts.uri.path() == "/slow" {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+            let header = |name: &str| {
+                parts
+                    .headers
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("-")
+                    .to_string()
+            };
+            let line = format!(
+                "{} {} {} {}",
+                parts.method,
+                parts.uri,
+                header("x-token"),
+                header("x-forwarded-host")
+            );
+            let body = body.collect().await.unwrap().to_bytes();
+            let body = format!("{line} {}", String::from_utf8_lossy(&body));
+            ([("x-internal", "1"), ("x-upstream", "legacy")], body)
+        }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    #[tokio::test]
+    async fn proxy_route_should_work() -> anyhow::Result<()> {
+        let addr = upstream().await;
+        let config = format!(
+            r#"
+        name: proxied
+        max_body_size: 16
+        body_read_timeout: 100ms
+        routes:
+          /items/{{id}}:
+            - method: POST
+              proxy:
+                url: http://{addr}/api/items/{{id}}
+                headers:
+                  x-token: secret
+                response_headers:
+                  x-internal: ""
+          /old/{{*rest}}:
+            - method: GET
+              proxy: http://{addr}
+          /slow:
+            - method: GET
+              proxy:
+                url: http://{addr}/slow
+                timeout: 50ms
+        "#
+        );
+        let config: ProjectConfig = serde_yml::from_str(&config)?;
+        let router = SwappableAppRouter::try_new("", config)?;
+        let app = DinoServer::new(vec![TenentRouter::new("localhost", router)]).router();
+        let send = |method: &str, uri: &str, body: &'static str| {
+            let req = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(HOST, "localhost")
+                .body(Body::from(body))
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap() }
+        };
+
+        let res = send("POST", "/items/1?v=2", "hello").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-upstream"], "legacy");
+        assert!(!res.headers().contains_key("x-internal"));
+        let body = res.into_body().collect().await?.to_bytes();
+        assert_eq!(body, "POST /api/items/1?v=2 secret localhost hello");
+
+        let res = send("GET", "/old/a/b", "").await;
+        let body = res.into_body().collect().await?.to_bytes();
+        assert_eq!(body, "GET /old/a/b - localhost ");
+
+        let res = send("POST", "/items/1", "hello, this is too large").await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let res = send("GET", "/slow", "").await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        // a client that never finishes its body
+        let req = axum::http::Request::builder()
+            .method("POST")
+            .uri("/items/1")
+            .header(HOST, "localhost")
+            .body(Body::new(Pending))?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+
+        Ok(())
+    }
+
+    struct Pending;
+
+    impl HttpBody for Pending {
+        type Data = axum::body::Bytes;
+        type Error = std::convert::Infallible;
+
+        fn poll_frame(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn proxy_config_should_reject_https() {
+        let ret = serde_yml::from_str::<crate::ProjectRoute>("method: GET\nproxy: https://legacy");
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn proxy_config_should_reject_js_rate_limit_key() {
+        let config = r#"
+        name: proxied
+        rate_limit:
+          requests: 1
+          per: 1s
+          key: js:clientKey
+        routes:
+          /old:
+            - method: GET
+              proxy: http://legacy
+        "#;
+        let config: ProjectConfig = serde_yml::from_str(config).unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("js key"));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::RateLimitConfig;
+use dashmap::DashMap;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// full buckets are dropped once there are more buckets than this
+const MAX_BUCKETS: usize = 100_000;
+/// new buckets between two purges, so a purge is amortised over many requests
+const PURGE_EVERY: usize = 10_000;
+
+/// token buckets of all tenants, shared by all clones
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    buckets: Arc<DashMap<String, Bucket>>,
+    created: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// time for an empty bucket to be full again
+    refill: Duration,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// take a token from the bucket of the key, or the time until the next token
+    pub fn check(&self, key: &str, limit: &RateLimitConfig) -> Result<(), Duration> {
+        self.check_at(key, limit, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, limit: &RateLimitConfig, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(limit.burst.unwrap_or(limit.requests).max(1));
+        let rate = f64::from(limit.requests) / limit.per.as_secs_f64();
+        if !self.buckets.contains_key(key) {
+            let created = self.created.fetch_add(1, Ordering::Relaxed);
+            if created.is_multiple_of(PURGE_EVERY) && self.buckets.len() >= MAX_BUCKETS {
+                self.purge(now);
+            }
+        }
+
+        let mut bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            refill: Duration::from_secs_f64(capacity / rate),
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+
+    /// drop the buckets full again, each by the window of its own limit
+    fn purge(&self, now: Instant) {
+        self.buckets
+            .retain(|_, b| now.saturating_duration_since(b.updated) < b.refill);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RateLimitKey;
+
+    fn limit(requests: u32, burst: Option<u32>) -> RateLimitConfig {
+        RateLimitConfig {
+            requests,
+            per: Duration::from_secs(1),
+            burst,
+            key: RateLimitKey::Ip,
+        }
+    }
+
+    #[test]
+    fn rate_limiter_should_work() {
+        let limiter = RateLimiter::new();
+        let limit = limit(2, None);
+        let now = Instant::now();
+
+        assert!(limiter.check_at("a", &limit, now).is_ok());
+        assert!(limiter.check_at("a", &limit, now).is_ok());
+        assert_eq!(
+            limiter.check_at("a", &limit, now),
+            Err(Duration::from_millis(500))
+        );
+        // other keys have their own bucket
+        assert!(limiter.check_at("b", &limit, now).is_ok());
+
+        // a token every 500ms
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.check_at("a", &limit, later).is_ok());
+        assert!(limiter.check_at("a", &limit, later).is_err());
+    }
+
+    #[test]
+    fn rate_limiter_should_allow_burst() {
+        let limiter = RateLimiter::new();
+        let limit = limit(1, Some(3));
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at("a", &limit, now).is_ok());
+        }
+        assert_eq!(
+            limiter.check_at("a", &limit, now),
+            Err(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn rate_limiter_purge_should_keep_buckets_not_full() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+        assert!(limiter.check_at("fast", &limit(1, None), now).is_ok());
+        // refilled in 10s
+        assert!(limiter.check_at("slow", &limit(1, Some(10)), now).is_ok());
+
+        limiter.purge(now + Duration::from_secs(2));
+        assert!(!limiter.buckets.contains_key("fast"));
+        assert!(limiter.buckets.contains_key("slow"));
+    }
+}
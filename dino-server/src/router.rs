@@ -1,74 +1,206 @@
-use crate::{config::ProjectRoutes, AppError};
+use crate::{
+    config::{ProjectConfig, ProjectRoute, ProjectRoutes, RedirectConfig},
+    metrics, AppError, ResponseCache,
+};
 use arc_swap::ArcSwap;
 use axum::http::Method;
 use matchit::{Match, Router};
-use std::{ops::Deref, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+use tracing::instrument;
+
+/// number of deployed versions kept for rollback by default
+pub const DEFAULT_MAX_VERSIONS: usize = 10;
 
 #[allow(unused)]
 #[derive(Clone, Debug)]
 pub struct SwappableAppRouter {
     pub inner: Arc<ArcSwap<AppRouterInner>>,
+    // deployed versions, the latest one is at the back
+    history: Arc<Mutex<VecDeque<Arc<AppRouterInner>>>>,
+    max_versions: usize,
 }
 
 #[derive(Clone, Debug)]
 pub struct AppRouterInner {
+    pub version: String,
     pub code: String,
     pub routes: Router<MethodRoute>,
+    pub rules: Rules,
+    pub config: ProjectConfig,
+    /// cleared when the version is replaced by another one
+    pub cache: Arc<ResponseCache>,
 }
 
 #[allow(unused)]
 #[derive(Clone)]
 pub struct AppRouter(Arc<AppRouterInner>);
 
+/// redirects and rewrites of a project, evaluated before the routes
+#[derive(Clone, Default, Debug)]
+pub struct Rules {
+    pub redirects: Router<RedirectConfig>,
+    pub rewrites: Router<String>,
+}
+
 #[allow(unused)]
 #[derive(Clone, Default, Debug)]
 pub struct MethodRoute {
-    pub get: Option<String>,
-    pub post: Option<String>,
-    pub put: Option<String>,
-    pub delete: Option<String>,
-    pub patch: Option<String>,
-    pub head: Option<String>,
-    pub options: Option<String>,
-    pub connect: Option<String>,
-    pub trace: Option<String>,
+    pub get: Option<ProjectRoute>,
+    pub post: Option<ProjectRoute>,
+    pub put: Option<ProjectRoute>,
+    pub delete: Option<ProjectRoute>,
+    pub patch: Option<ProjectRoute>,
+    pub head: Option<ProjectRoute>,
+    pub options: Option<ProjectRoute>,
+    pub connect: Option<ProjectRoute>,
+    pub trace: Option<ProjectRoute>,
 }
 
 impl SwappableAppRouter {
-    pub fn try_new(code: impl Into<String>, routes: ProjectRoutes) -> anyhow::Result<Self> {
-        let router = Self::get_router(routes)?;
+    /// the version is the hash of the code
+    pub fn try_new(code: impl Into<String>, config: ProjectConfig) -> anyhow::Result<Self> {
+        let code = code.into();
+        Self::try_new_with_version(calc_version(&code), code, config)
+    }
+
+    pub fn try_new_with_version(
+        version: impl Into<String>,
+        code: impl Into<String>,
+        config: ProjectConfig,
+    ) -> anyhow::Result<Self> {
+        config.validate()?;
+        let router = Self::get_router(&config.routes)?;
+        let rules = Self::get_rules(&config)?;
+        let inner = Arc::new(AppRouterInner::new(version, code, router, rules, config));
         Ok(Self {
-            inner: Arc::new(ArcSwap::new(Arc::new(AppRouterInner::new(code, router)))),
+            inner: Arc::new(ArcSwap::new(inner.clone())),
+            history: Arc::new(Mutex::new(VecDeque::from([inner]))),
+            max_versions: DEFAULT_MAX_VERSIONS,
         })
     }
 
-    pub fn swap(&self, code: impl Into<String>, routes: ProjectRoutes) -> anyhow::Result<()> {
-        let router = Self::get_router(routes)?;
-        self.inner
-            .store(Arc::new(AppRouterInner::new(code, router)));
+    /// keep at most `max_versions` deployed versions (at least one) for rollback
+    pub fn with_max_versions(mut self, max_versions: usize) -> Self {
+        self.max_versions = max_versions.max(1);
+        let mut history = self.history.lock().unwrap();
+        while history.len() > self.max_versions {
+            history.pop_front();
+        }
+        drop(history);
+        self
+    }
+
+    pub fn swap(&self, code: impl Into<String>, config: ProjectConfig) -> anyhow::Result<()> {
+        let code = code.into();
+        self.deploy(calc_version(&code), code, config)
+    }
+
+    /// install a new version and make it active, deploying an existing version replaces it
+    pub fn deploy(
+        &self,
+        version: impl Into<String>,
+        code: impl Into<String>,
+        config: ProjectConfig,
+    ) -> anyhow::Result<()> {
+        let built = config
+            .validate()
+            .and_then(|_| Self::get_router(&config.routes))
+            .and_then(|router| Ok((router, Self::get_rules(&config)?)));
+        let (router, rules) = match built {
+            Ok(built) => built,
+            Err(e) => {
+                metrics().record_reload(&config.name, false);
+                return Err(e);
+            }
+        };
+        metrics().record_reload(&config.name, true);
+        let inner = Arc::new(AppRouterInner::new(version, code, router, rules, config));
+
+        let mut history = self.history.lock().unwrap();
+        history.retain(|v| v.version != inner.version);
+        history.push_back(inner.clone());
+        while history.len() > self.max_versions {
+            history.pop_front();
+        }
+        self.inner.swap(inner).cache.clear();
+
+        Ok(())
+    }
+
+    /// make a deployed version active again
+    pub fn activate(&self, version: &str) -> Result<(), AppError> {
+        let history = self.history.lock().unwrap();
+        let inner = history
+            .iter()
+            .find(|v| v.version == version)
+            .ok_or_else(|| AppError::VersionNotFound(version.to_string()))?;
+        let previous = self.inner.swap(inner.clone());
+        if !Arc::ptr_eq(&previous, inner) {
+            previous.cache.clear();
+        }
 
         Ok(())
     }
 
+    /// activate the version deployed before the active one
+    pub fn rollback(&self) -> Result<String, AppError> {
+        let active = self.active_version();
+        let previous = {
+            let history = self.history.lock().unwrap();
+            history
+                .iter()
+                .position(|v| v.version == active)
+                .and_then(|pos| pos.checked_sub(1))
+                .map(|pos| history[pos].version.clone())
+                .ok_or_else(|| AppError::VersionNotFound(format!("before {active}")))?
+        };
+        self.activate(&previous)?;
+
+        Ok(previous)
+    }
+
+    pub fn active_version(&self) -> String {
+        self.inner.load().version.clone()
+    }
+
+    /// deployed versions, the oldest first
+    pub fn versions(&self) -> Vec<String> {
+        let history = self.history.lock().unwrap();
+        history.iter().map(|v| v.version.clone()).collect()
+    }
+
     pub fn load(&self) -> AppRouter {
         AppRouter(self.inner.load_full())
     }
 
-    fn get_router(routes: ProjectRoutes) -> anyhow::Result<Router<MethodRoute>> {
+    fn get_router(routes: &ProjectRoutes) -> anyhow::Result<Router<MethodRoute>> {
         let mut router = Router::new();
         for (path, methods) in routes {
             let mut method_route = MethodRoute::default();
-            for method in methods {
-                match method.method {
-                    Method::GET => method_route.get = Some(method.handler),
-                    Method::POST => method_route.post = Some(method.handler),
-                    Method::PUT => method_route.put = Some(method.handler),
-                    Method::DELETE => method_route.delete = Some(method.handler),
-                    Method::PATCH => method_route.patch = Some(method.handler),
-                    Method::HEAD => method_route.head = Some(method.handler),
-                    Method::OPTIONS => method_route.options = Some(method.handler),
-                    Method::CONNECT => method_route.connect = Some(method.handler),
-                    Method::TRACE => method_route.trace = Some(method.handler),
+            for mut method in methods.iter().cloned() {
+                method.path = path.clone();
+                match (&method.proxy, method.handler.is_empty()) {
+                    (Some(_), false) => {
+                        anyhow::bail!("route {path} has both a handler and a proxy")
+                    }
+                    (None, true) => anyhow::bail!("route {path} has no handler"),
+                    _ => {}
+                }
+                match method.method.clone() {
+                    Method::GET => method_route.get = Some(method),
+                    Method::POST => method_route.post = Some(method),
+                    Method::PUT => method_route.put = Some(method),
+                    Method::DELETE => method_route.delete = Some(method),
+                    Method::PATCH => method_route.patch = Some(method),
+                    Method::HEAD => method_route.head = Some(method),
+                    Method::OPTIONS => method_route.options = Some(method),
+                    Method::CONNECT => method_route.connect = Some(method),
+                    Method::TRACE => method_route.trace = Some(method),
                     v => unreachable!("unsupported method {v}"),
                 }
             }
@@ -76,13 +208,42 @@ impl SwappableAppRouter {
         }
         Ok(router)
     }
+
+    fn get_rules(config: &ProjectConfig) -> anyhow::Result<Rules> {
+        let mut rules = Rules::default();
+        for (path, redirect) in &config.redirects {
+            if ![301, 302, 307, 308].contains(&redirect.status) {
+                anyhow::bail!("redirect {path} has invalid status {}", redirect.status);
+            }
+            let mut redirect = redirect.clone();
+            redirect.path = path.clone();
+            rules.redirects.insert(path, redirect)?;
+        }
+        for (path, to) in &config.rewrites {
+            if !to.starts_with('/') {
+                anyhow::bail!("rewrite {path} must be a path, got {to}");
+            }
+            rules.rewrites.insert(path, to.clone())?;
+        }
+        Ok(rules)
+    }
 }
 
 impl AppRouterInner {
-    pub fn new(code: impl Into<String>, routes: Router<MethodRoute>) -> Self {
+    pub fn new(
+        version: impl Into<String>,
+        code: impl Into<String>,
+        routes: Router<MethodRoute>,
+        rules: Rules,
+        config: ProjectConfig,
+    ) -> Self {
         Self {
+            version: version.into(),
             code: code.into(),
             routes,
+            rules,
+            cache: Arc::new(ResponseCache::new(config.max_cache_size())),
+            config,
         }
     }
 }
@@ -97,25 +258,58 @@ impl Deref for AppRouter {
 
 #[allow(unused)]
 impl AppRouter {
-    #[allow(elided_named_lifetimes)]
+    /// the location and the rule if the path is redirected, paths are relative to the mount
+    pub fn redirect(
+        &self,
+        path: &str,
+        query: Option<&str>,
+        mount: &str,
+    ) -> Option<(String, &RedirectConfig)> {
+        let matched = self.rules.redirects.at(path).ok()?;
+        let redirect = matched.value;
+
+        let mut location = fill_params(&redirect.to, matched.params.iter());
+        if location.starts_with('/') {
+            // `//host` or `/\host` from a param would leave the site
+            let path = location.trim_start_matches(['/', '\\']);
+            location = format!("{mount}/{path}");
+        }
+        if let Some(query) = query.filter(|_| redirect.preserve_query) {
+            location.push(if location.contains('?') { '&' } else { '?' });
+            location.push_str(query);
+        }
+
+        Some((location, redirect))
+    }
+
+    /// the path served instead of the requested one
+    pub fn rewrite<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        match self.rules.rewrites.at(path) {
+            Ok(matched) => Cow::Owned(fill_params(matched.value, matched.params.iter())),
+            Err(_) => Cow::Borrowed(path),
+        }
+    }
+
+    #[instrument(skip(self), fields(version = %self.version))]
     pub fn match_it<'a>(
         &'a self,
         method: Method,
         path: &'a str,
-    ) -> Result<Match<&'a str>, AppError> {
+    ) -> Result<Match<'a, 'a, &'a ProjectRoute>, AppError> {
         let Ok(ret) = self.routes.at(path) else {
             return Err(AppError::RoutePathNotFound(path.to_string()));
         };
         let s = match method {
-            Method::GET => ret.value.get.as_deref(),
-            Method::HEAD => ret.value.head.as_deref(),
-            Method::DELETE => ret.value.delete.as_deref(),
-            Method::OPTIONS => ret.value.options.as_deref(),
-            Method::PATCH => ret.value.patch.as_deref(),
-            Method::POST => ret.value.post.as_deref(),
-            Method::PUT => ret.value.put.as_deref(),
-            Method::TRACE => ret.value.trace.as_deref(),
-            Method::CONNECT => ret.value.connect.as_deref(),
+            Method::GET => ret.value.get.as_ref(),
+            // the body of the get handler is dropped for head requests
+            Method::HEAD => ret.value.head.as_ref().or(ret.value.get.as_ref()),
+            Method::DELETE => ret.value.delete.as_ref(),
+            Method::OPTIONS => ret.value.options.as_ref(),
+            Method::PATCH => ret.value.patch.as_ref(),
+            Method::POST => ret.value.post.as_ref(),
+            Method::PUT => ret.value.put.as_ref(),
+            Method::TRACE => ret.value.trace.as_ref(),
+            Method::CONNECT => ret.value.connect.as_ref(),
             _ => unreachable!(),
         }
         .ok_or_else(|| AppError::RouteMethodNotAllowed(method))?;
@@ -127,6 +321,27 @@ impl AppRouter {
     }
 }
 
+/// substitute the params of a matched path, `{name}` and `{*name}` alike
+pub(crate) fn fill_params<'a>(
+    template: &str,
+    params: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> String {
+    let mut filled = template.to_string();
+    for (name, value) in params {
+        filled = filled
+            .replace(&format!("{{{name}}}"), value)
+            .replace(&format!("{{*{name}}}"), value);
+    }
+    filled
+}
+
+/// blake3 hash of the code, in the same length as the build hash of dino
+pub(crate) fn calc_version(code: &str) -> String {
+    let mut version = blake3::hash(code.as_bytes()).to_string();
+    version.truncate(16);
+    version
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,17 +350,16 @@ mod tests {
     #[test]
     fn app_router_match_should_work() {
         let config = include_str!("../fixtures/config.yml");
-        let routes: ProjectConfig = serde_yml::from_str(config).unwrap();
-        let routes = routes.routes;
-        let router = SwappableAppRouter::try_new("", routes).unwrap();
+        let config: ProjectConfig = serde_yml::from_str(config).unwrap();
+        let router = SwappableAppRouter::try_new("", config).unwrap();
         let app_router = router.load();
         let m = app_router.match_it(Method::GET, "/api/hello/1").unwrap();
 
-        assert_eq!(m.value, "hello");
+        assert_eq!(m.value.handler, "hello");
         assert_eq!(m.params.get("id"), Some("1"));
 
         let m = app_router.match_it(Method::POST, "/api/abc/1").unwrap();
-        assert_eq!(m.value, "hello");
+        assert_eq!(m.value.handler, "hello");
         assert_eq!(m.params.get("name"), Some("abc"));
         assert_eq!(m.params.get("id"), Some("1"));
     }
@@ -153,25 +367,123 @@ mod tests {
     #[test]
     fn app_router_swap_should_work() {
         let config = include_str!("../fixtures/config.yml");
-        let routes: ProjectConfig = serde_yml::from_str(config).unwrap();
-        let routes = routes.routes;
-        let router = SwappableAppRouter::try_new("", routes).unwrap();
+        let config: ProjectConfig = serde_yml::from_str(config).unwrap();
+        let router = SwappableAppRouter::try_new("", config).unwrap();
         let app_router = router.load();
 
         let m = app_router.match_it(Method::POST, "/api/abc/1").unwrap();
-        assert_eq!(m.value, "hello");
+        assert_eq!(m.value.handler, "hello");
         assert_eq!(m.params.get("name"), Some("abc"));
         assert_eq!(m.params.get("id"), Some("1"));
 
         let new_config = include_str!("../fixtures/config1.yml");
-        let new_routes: ProjectConfig = serde_yml::from_str(new_config).unwrap();
-        let new_routes = new_routes.routes;
-        router.swap("", new_routes).unwrap();
+        let new_config: ProjectConfig = serde_yml::from_str(new_config).unwrap();
+        router.swap("", new_config).unwrap();
         let app_router = router.load();
 
         let m = app_router.match_it(Method::POST, "/api/abc/1").unwrap();
-        assert_eq!(m.value, "handler2");
+        assert_eq!(m.value.handler, "handler2");
         assert_eq!(m.params.get("name"), Some("abc"));
         assert_eq!(m.params.get("id"), Some("1"));
     }
+
+    #[test]
+    fn app_router_rollback_should_work() {
+        let config: ProjectConfig =
+            serde_yml::from_str(include_str!("../fixtures/config.yml")).unwrap();
+        let new_config: ProjectConfig =
+            serde_yml::from_str(include_str!("../fixtures/config1.yml")).unwrap();
+        let router = SwappableAppRouter::try_new_with_version("v1", "", config).unwrap();
+        router.deploy("v2", "", new_config).unwrap();
+
+        assert_eq!(router.versions(), ["v1", "v2"]);
+        assert_eq!(router.active_version(), "v2");
+
+        assert_eq!(router.rollback().unwrap(), "v1");
+        let app_router = router.load();
+        let m = app_router.match_it(Method::POST, "/api/abc/1").unwrap();
+        assert_eq!(m.value.handler, "hello");
+
+        assert!(router.rollback().is_err());
+        router.activate("v2").unwrap();
+        assert_eq!(router.active_version(), "v2");
+        assert!(router.activate("v3").is_err());
+    }
+
+    #[test]
+    fn app_router_should_keep_max_versions() {
+        let config: ProjectConfig =
+            serde_yml::from_str(include_str!("../fixtures/config.yml")).unwrap();
+        let router = SwappableAppRouter::try_new_with_version("v1", "", config.clone())
+            .unwrap()
+            .with_max_versions(2);
+        router.deploy("v2", "", config.clone()).unwrap();
+        router.deploy("v3", "", config.clone()).unwrap();
+        assert_eq!(router.versions(), ["v2", "v3"]);
+
+        // redeploying a retained version moves it to the latest
+        router.deploy("v2", "", config).unwrap();
+        assert_eq!(router.versions(), ["v3", "v2"]);
+        assert_eq!(router.active_version(), "v2");
+
+        router.activate("v3").unwrap();
+        assert_eq!(router.load().version, "v3");
+    }
+
+    #[test]
+    fn app_router_rules_should_work() {
+        let config = r#"
+        name: rules
+        redirects:
+          /old/{id}:
+            to: /api/hello/{id}
+            status: 308
+          /docs/{*rest}:
+            to: https://docs.example.com/{rest}
+            preserve_query: false
+          /go/{*rest}:
+            to: /{rest}
+        rewrites:
+          /hello/{id}: /api/hello/{id}
+        routes:
+          /api/hello/{id}:
+            - method: GET
+              handler: hello
+        "#;
+        let config: ProjectConfig = serde_yml::from_str(config).unwrap();
+        let router = SwappableAppRouter::try_new("", config).unwrap().load();
+
+        let (location, redirect) = router.redirect("/old/1", Some("a=1"), "/app").unwrap();
+        assert_eq!(location, "/app/api/hello/1?a=1");
+        assert_eq!(redirect.status, 308);
+        assert_eq!(redirect.path, "/old/{id}");
+
+        let (location, redirect) = router.redirect("/docs/a/b", Some("a=1"), "").unwrap();
+        assert_eq!(location, "https://docs.example.com/a/b");
+        assert_eq!(redirect.status, 301);
+        assert!(router.redirect("/api/hello/1", None, "").is_none());
+
+        // a param can't turn the location into another host
+        let (location, _) = router.redirect("/go//evil.com", None, "").unwrap();
+        assert_eq!(location, "/evil.com");
+        let (location, _) = router.redirect("/go/\\evil.com", None, "/app").unwrap();
+        assert_eq!(location, "/app/evil.com");
+
+        assert_eq!(router.rewrite("/hello/1"), "/api/hello/1");
+        assert_eq!(router.rewrite("/api/hello/1"), "/api/hello/1");
+    }
+
+    #[test]
+    fn app_router_should_reject_invalid_rules() {
+        let config = r#"
+        name: rules
+        redirects:
+          /old:
+            to: /new
+            status: 200
+        routes: {}
+        "#;
+        let config: ProjectConfig = serde_yml::from_str(config).unwrap();
+        assert!(SwappableAppRouter::try_new("", config).is_err());
+    }
 }
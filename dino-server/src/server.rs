@@ -0,0 +1,287 @@
+use crate::{
+    compression_layer, handler::handler, probe_router, shutdown::drain_deadline, AccessLogLayer,
+    AppState, CorsLayer, MetricsLayer, ProbeConfig, RequestIdLayer, ServerHandle, ServerTimeLayer,
+};
+use crate::{CertStore, TenentRouter, TlsListener, DEFAULT_DRAIN_TIMEOUT};
+use axum::{
+    extract::Request,
+    response::IntoResponse,
+    routing::{any, Route},
+    serve::{Listener, ListenerExt},
+    Router,
+};
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tower::{Layer, Service};
+use tower_http::decompression::RequestDecompressionLayer;
+use tracing::info;
+use typed_builder::TypedBuilder;
+
+type LayerFn = Box<dyn Fn(Router) -> Router + Send + Sync>;
+
+/// serves the tenants of an `AppState`, either on its own listener or embedded as an axum `Router`
+#[derive(TypedBuilder)]
+pub struct DinoServer {
+    #[builder(setter(into))]
+    state: AppState,
+    #[builder(default = SocketAddr::from(([0, 0, 0, 0], 8080)))]
+    addr: SocketAddr,
+    /// time for in-flight requests to finish on shutdown
+    #[builder(default = DEFAULT_DRAIN_TIMEOUT)]
+    drain_timeout: Duration,
+    /// add the `x-server-time` header to every response
+    #[builder(default = true)]
+    server_time: bool,
+    /// compress responses and decompress request bodies
+    #[builder(default = true)]
+    compression: bool,
+    /// log every request, inside the request id so it is logged as well
+    #[builder(default)]
+    access_log: Option<AccessLogLayer>,
+    /// paths of the health, readiness and info endpoints
+    #[builder(default)]
+    probes: ProbeConfig,
+    /// share a token to shut down several servers together
+    #[builder(default)]
+    shutdown: CancellationToken,
+    #[builder(default, setter(skip))]
+    layers: Vec<LayerFn>,
+}
+
+impl DinoServer {
+    pub fn new(routers: Vec<TenentRouter>) -> Self {
+        Self::builder().state(routers).build()
+    }
+
+    /// wrap the tenant routes with a tower layer, layers added later run first
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.layers
+            .push(Box::new(move |router| router.layer(layer.clone())));
+        self
+    }
+
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// the tenant routes, ready to be merged into another axum app or called as a `tower::Service`
+    pub fn router(&self) -> Router {
+        let mut app = Router::new()
+            .route("/{*path}", any(handler))
+            .with_state(self.state.clone())
+            .layer(CorsLayer::new(self.state.clone()));
+        if self.compression {
+            app = app
+                .layer(RequestDecompressionLayer::new())
+                .layer(compression_layer());
+        }
+        if self.server_time {
+            app = app.layer(ServerTimeLayer);
+        }
+        app = app.layer(MetricsLayer);
+        for layer in &self.layers {
+            app = layer(app);
+        }
+        // probes are not wrapped by the layers of the tenants, e.g. an auth layer
+        app = app.merge(probe_router(&self.probes).with_state(self.state.clone()));
+
+        if let Some(access_log) = &self.access_log {
+            app = app.layer(access_log.clone());
+        }
+
+        // outermost, so every layer logs and sees the request id
+        app.layer(RequestIdLayer)
+    }
+
+    /// bind the address and serve in the background
+    pub async fn spawn(self) -> anyhow::Result<ServerHandle> {
+        let listener = TcpListener::bind(self.addr).await?;
+        self.spawn_with_listener(listener)
+    }
+
+    /// terminate tls with the certificate picked by the SNI of the client
+    pub async fn spawn_tls(self, certs: &CertStore) -> anyhow::Result<ServerHandle> {
+        let listener = TcpListener::bind(self.addr).await?;
+        let listener = TlsListener::new(listener, certs.server_config())?;
+        self.spawn_with_listener(listener)
+    }
+
+    /// serve on an already bound listener in the background
+    pub fn spawn_with_listener<L>(self, listener: L) -> anyhow::Result<ServerHandle>
+    where
+        L: Listener<Addr = SocketAddr>,
+    {
+        let app = self.router();
+        spawn_router(listener, app, self.drain_timeout, self.shutdown)
+    }
+}
+
+pub(crate) fn spawn_router<L>(
+    listener: L,
+    app: Router,
+    drain_timeout: Duration,
+    token: CancellationToken,
+) -> anyhow::Result<ServerHandle>
+where
+    L: Listener<Addr = SocketAddr>,
+{
+    let addr = listener.local_addr()?;
+    info!("Listening on: {}", addr);
+
+    let shutdown = token.clone();
+    let task = tokio::spawn(async move {
+        // axum provides the client address of any listener wrapped by `tap_io`
+        let listener = listener.tap_io(|_| {});
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        let server =
+            axum::serve(listener, app).with_graceful_shutdown(shutdown.clone().cancelled_owned());
+        tokio::select! {
+            ret = server => ret?,
+            _ = drain_deadline(shutdown, drain_timeout) => {}
+        }
+        info!("Server on {} stopped", addr);
+
+        Ok(())
+    });
+
+    Ok(ServerHandle::new(addr, token, task))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProjectConfig, SwappableAppRouter};
+    use axum::{body::Body, http::StatusCode, middleware::map_response, response::Response};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    const CODE: &str = r#"(function(){
+        return { hello: async (req) => ({ status: 200, headers: {}, body: req.params.id }) };
+    })()"#;
+
+    fn server() -> DinoServer {
+        let config: ProjectConfig =
+            serde_yml::from_str(include_str!("../fixtures/config.yml")).unwrap();
+        let router = SwappableAppRouter::try_new(CODE, config).unwrap();
+        DinoServer::new(vec![TenentRouter::new("localhost", router)])
+    }
+
+    async fn powered_by(mut res: Response) -> Response {
+        res.headers_mut()
+            .insert("x-powered-by", "dino".parse().unwrap());
+        res
+    }
+
+    fn request(host: &str, uri: &str) -> Request {
+        Request::builder()
+            .uri(uri)
+            .header("host", host)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn dino_server_router_should_work() -> anyhow::Result<()> {
+        let app = server().layer(map_response(powered_by)).router();
+
+        let res = app
+            .clone()
+            .oneshot(request("localhost", "/api/hello/42"))
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-powered-by"], "dino");
+        assert!(res.headers().contains_key("x-server-time"));
+        let body = res.into_body().collect().await?.to_bytes();
+        assert_eq!(body, "42");
+
+        let res = app.oneshot(request("unknown", "/api/hello/42")).await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dino_server_compression_should_work() -> anyhow::Result<()> {
+        use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+        use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+        use std::io::{Read, Write};
+
+        let code = r#"(function(){
+            return { hello: async (req) => ({ status: 200, headers: {}, body: req.body.repeat(8) }) };
+        })()"#;
+        let config: ProjectConfig = serde_yml::from_str(include_str!("../fixtures/config.yml"))?;
+        let router = SwappableAppRouter::try_new(code, config)?;
+        let app = DinoServer::new(vec![TenentRouter::new("localhost", router)]).router();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"hello dino ")?;
+        let req = Request::builder()
+            .method("POST")
+            .uri("/api/hello/42")
+            .header("host", "localhost")
+            .header(CONTENT_ENCODING, "gzip")
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::from(encoder.finish()?))?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+
+        let body = res.into_body().collect().await?.to_bytes();
+        let mut decoded = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut decoded)?;
+        assert_eq!(decoded, "hello dino ".repeat(8));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dino_server_should_serve_mounted_tenant() -> anyhow::Result<()> {
+        let code = r#"(function(){
+            return { hello: async (req) => ({ status: 200, headers: {}, body: `${req.mount}:${req.params.id}:${req.request_id}` }) };
+        })()"#;
+        let config: ProjectConfig = serde_yml::from_str(include_str!("../fixtures/config.yml"))?;
+        let router = SwappableAppRouter::try_new(code, config)?;
+        let app = DinoServer::new(vec![
+            TenentRouter::new("localhost", router).with_mount("/svc/hello")
+        ])
+        .router();
+
+        let res = app
+            .clone()
+            .oneshot(request("localhost", "/svc/hello/api/hello/42"))
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let id = res.headers()["x-request-id"].to_str()?.to_string();
+        let body = res.into_body().collect().await?.to_bytes();
+        assert_eq!(body, format!("/svc/hello:42:{id}"));
+
+        let res = app.oneshot(request("localhost", "/api/hello/42")).await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dino_server_should_nest_in_axum_app() -> anyhow::Result<()> {
+        let app = Router::new()
+            .route("/ping", any(|| async { "pong" }))
+            .fallback_service(server().router());
+
+        let res = app.clone().oneshot(request("localhost", "/ping")).await?;
+        let body = res.into_body().collect().await?.to_bytes();
+        assert_eq!(body, "pong");
+
+        let res = app.oneshot(request("localhost", "/api/hello/7")).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        Ok(())
+    }
+}
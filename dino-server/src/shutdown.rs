@@ -0,0 +1,92 @@
+use std::{net::SocketAddr, time::Duration};
+use tokio::{signal, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// time to wait for in-flight requests after a shutdown was triggered
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// handle of a running server, used to shut it down gracefully
+#[derive(Debug)]
+pub struct ServerHandle {
+    addr: SocketAddr,
+    token: CancellationToken,
+    task: JoinHandle<anyhow::Result<()>>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        addr: SocketAddr,
+        token: CancellationToken,
+        task: JoinHandle<anyhow::Result<()>>,
+    ) -> Self {
+        Self { addr, token, task }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// stop accepting connections and drain in-flight requests
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    /// cancelled once shutdown is triggered, background tasks should stop on it
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// wait until the server stopped
+    pub async fn wait(self) -> anyhow::Result<()> {
+        self.task.await?
+    }
+
+    /// shut down gracefully on SIGINT or SIGTERM, then wait until the server stopped
+    pub async fn wait_for_signal(self) -> anyhow::Result<()> {
+        tokio::select! {
+            _ = shutdown_signal() => {
+                info!("Shutdown signal received, draining connections");
+                self.shutdown();
+            }
+            _ = self.token.cancelled() => {}
+        }
+
+        self.wait().await
+    }
+}
+
+/// resolve on Ctrl-C or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// resolve once the drain timeout elapsed after the shutdown was triggered
+pub(crate) async fn drain_deadline(token: CancellationToken, timeout: Duration) {
+    token.cancelled().await;
+    tokio::time::sleep(timeout).await;
+    warn!(
+        "Drain timeout of {:?} elapsed, dropping connections",
+        timeout
+    );
+}
@@ -0,0 +1,299 @@
+use crate::{AppRouter, SwappableAppRouter};
+use arc_swap::ArcSwapOption;
+use axum::http::{header::COOKIE, HeaderMap};
+use rand::Rng;
+use std::{fmt, sync::Arc};
+
+/// cookie to keep a client on the variant it was assigned to
+pub const VARIANT_COOKIE: &str = "dino-variant";
+/// host of the tenant serving requests no other tenant matches
+pub const DEFAULT_HOST: &str = "*";
+
+/// a tenant serves its primary bundle, and optionally a canary bundle for a share of the traffic
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub primary: SwappableAppRouter,
+    // shared by all clones, so it can be changed while serving
+    canary: Arc<ArcSwapOption<Canary>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Canary {
+    pub router: SwappableAppRouter,
+    /// percentage of the traffic served by the canary, 0 to 100
+    pub weight: u8,
+    /// requests with the same value of this header always get the same variant
+    pub sticky_header: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Primary,
+    Canary,
+}
+
+/// the router picked for a request, kept in the request extensions once picked
+#[derive(Clone)]
+pub struct Selected {
+    pub router: AppRouter,
+    pub variant: Variant,
+    /// true if the variant should be remembered by the client
+    pub new_assignment: bool,
+}
+
+impl Tenant {
+    pub fn new(primary: SwappableAppRouter) -> Self {
+        Self {
+            primary,
+            canary: Arc::new(ArcSwapOption::empty()),
+        }
+    }
+
+    pub fn canary(&self) -> Option<Arc<Canary>> {
+        self.canary.load_full()
+    }
+
+    pub fn set_canary(&self, canary: Option<Canary>) {
+        self.canary.store(canary.map(Arc::new));
+    }
+
+    /// true for clones of the same tenant, like the ones of its aliases
+    pub fn is_same(&self, other: &Tenant) -> bool {
+        Arc::ptr_eq(&self.canary, &other.canary)
+    }
+
+    /// a client keeps the variant from its cookie, then the sticky header decides,
+    /// otherwise the variant is picked randomly by the weight of the canary, a canary
+    /// without weight is drained, so its cookie is ignored
+    pub fn select(&self, headers: &HeaderMap) -> Selected {
+        let Some(canary) = self.canary() else {
+            return Selected::new(&self.primary, Variant::Primary, false);
+        };
+
+        let cookie = get_variant_cookie(headers)
+            .filter(|variant| *variant == Variant::Primary || canary.weight > 0);
+        if let Some(variant) = cookie {
+            let router = match variant {
+                Variant::Primary => &self.primary,
+                Variant::Canary => &canary.router,
+            };
+            return Selected::new(router, variant, false);
+        }
+
+        let sticky = canary
+            .sticky_header
+            .as_ref()
+            .and_then(|name| headers.get(name))
+            .map(|v| bucket(v.as_bytes()));
+        let (bucket, new_assignment) = match sticky {
+            Some(bucket) => (bucket, false),
+            None => (rand::thread_rng().gen_range(0..100), true),
+        };
+
+        if bucket < canary.weight {
+            Selected::new(&canary.router, Variant::Canary, new_assignment)
+        } else {
+            Selected::new(&self.primary, Variant::Primary, new_assignment)
+        }
+    }
+
+    /// make the active version of the canary the active version of the primary
+    pub fn promote_canary(&self) -> anyhow::Result<()> {
+        let Some(canary) = self.canary.swap(None) else {
+            anyhow::bail!("no canary to promote");
+        };
+        let inner = canary.router.load();
+        self.primary
+            .deploy(&inner.version, &inner.code, inner.config.clone())?;
+
+        Ok(())
+    }
+}
+
+impl Selected {
+    fn new(router: &SwappableAppRouter, variant: Variant, new_assignment: bool) -> Self {
+        Self {
+            router: router.load(),
+            variant,
+            new_assignment,
+        }
+    }
+}
+
+impl Variant {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Variant::Primary => "primary",
+            Variant::Canary => "canary",
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// the keys a host is looked up by, in order of precedence: the exact host,
+/// wildcards from the most to the least specific (`*.a.example.com`, `*.example.com`, ...),
+/// then the default host
+pub(crate) fn host_patterns(host: &str) -> Vec<String> {
+    let host = host.trim_end_matches('.').to_lowercase();
+    let mut patterns = vec![host.clone()];
+    let mut rest = host.as_str();
+    while let Some((_, parent)) = rest.split_once('.') {
+        if parent.is_empty() {
+            break;
+        }
+        patterns.push(format!("*.{parent}"));
+        rest = parent;
+    }
+    patterns.push(DEFAULT_HOST.to_string());
+
+    patterns
+}
+
+/// key of a tenant in the app state, a mounted tenant is keyed by host and path prefix
+pub(crate) fn tenant_key(host: &str, mount: Option<&str>) -> String {
+    let host = host.trim_end_matches('.').to_lowercase();
+    match mount.map(|m| m.trim_matches('/')) {
+        Some(mount) if !mount.is_empty() => format!("{host}/{mount}"),
+        _ => host,
+    }
+}
+
+/// the path prefixes a tenant may be mounted at, from the longest to the root (empty)
+pub(crate) fn mount_prefixes(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.trim_end_matches('/');
+    path.match_indices('/')
+        .map(|(i, _)| &path[..i])
+        .chain(std::iter::once(path))
+        .filter(|p| !p.is_empty())
+        .rev()
+        .chain(std::iter::once(""))
+}
+
+fn get_variant_cookie(headers: &HeaderMap) -> Option<Variant> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == VARIANT_COOKIE)
+        .and_then(|(_, value)| match value {
+            "primary" => Some(Variant::Primary),
+            "canary" => Some(Variant::Canary),
+            _ => None,
+        })
+}
+
+/// stable bucket in 0..100 for a sticky value
+fn bucket(value: &[u8]) -> u8 {
+    let hash = blake3::hash(value);
+    let n = u16::from_le_bytes([hash.as_bytes()[0], hash.as_bytes()[1]]);
+    (n % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProjectConfig;
+
+    fn router(version: &str) -> SwappableAppRouter {
+        let config: ProjectConfig =
+            serde_yml::from_str(include_str!("../fixtures/config.yml")).unwrap();
+        SwappableAppRouter::try_new_with_version(version, "", config).unwrap()
+    }
+
+    fn tenant(weight: u8) -> Tenant {
+        let tenant = Tenant::new(router("v1"));
+        tenant.set_canary(Some(Canary {
+            router: router("v2"),
+            weight,
+            sticky_header: Some("x-user-id".to_string()),
+        }));
+        tenant
+    }
+
+    #[test]
+    fn host_patterns_should_work() {
+        assert_eq!(
+            host_patterns("API.a.Example.com."),
+            [
+                "api.a.example.com",
+                "*.a.example.com",
+                "*.example.com",
+                "*.com",
+                "*"
+            ]
+        );
+        assert_eq!(host_patterns("localhost"), ["localhost", "*"]);
+    }
+
+    #[test]
+    fn mount_prefixes_should_work() {
+        let prefixes: Vec<_> = mount_prefixes("/svc/orders/1").collect();
+        assert_eq!(prefixes, ["/svc/orders/1", "/svc/orders", "/svc", ""]);
+        let prefixes: Vec<_> = mount_prefixes("/").collect();
+        assert_eq!(prefixes, [""]);
+
+        assert_eq!(
+            tenant_key("Dino.local", Some("/svc/orders/")),
+            "dino.local/svc/orders"
+        );
+        assert_eq!(tenant_key("dino.local", Some("/")), "dino.local");
+    }
+
+    #[test]
+    fn tenant_select_should_follow_weight() {
+        let headers = HeaderMap::new();
+
+        let selected = tenant(0).select(&headers);
+        assert_eq!(selected.variant, Variant::Primary);
+        assert!(selected.new_assignment);
+
+        let selected = tenant(100).select(&headers);
+        assert_eq!(selected.variant, Variant::Canary);
+        assert_eq!(selected.router.version, "v2");
+
+        let selected = Tenant::new(router("v1")).select(&headers);
+        assert_eq!(selected.variant, Variant::Primary);
+        assert!(!selected.new_assignment);
+    }
+
+    #[test]
+    fn tenant_select_should_be_sticky() {
+        let drained = tenant(0);
+        let tenant = tenant(50);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, "a=b; dino-variant=canary".parse().unwrap());
+        assert_eq!(tenant.select(&headers).variant, Variant::Canary);
+        // clients pinned to a canary without weight are moved back
+        let selected = drained.select(&headers);
+        assert_eq!(selected.variant, Variant::Primary);
+        assert!(selected.new_assignment);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-user-id", "42".parse().unwrap());
+        let variant = tenant.select(&headers).variant;
+        for _ in 0..10 {
+            let selected = tenant.select(&headers);
+            assert_eq!(selected.variant, variant);
+            assert!(!selected.new_assignment);
+        }
+    }
+
+    #[test]
+    fn tenant_promote_canary_should_work() {
+        let tenant = tenant(10);
+        tenant.promote_canary().unwrap();
+
+        assert!(tenant.canary().is_none());
+        assert_eq!(tenant.primary.active_version(), "v2");
+        assert_eq!(tenant.primary.versions(), ["v1", "v2"]);
+        assert!(tenant.promote_canary().is_err());
+    }
+}
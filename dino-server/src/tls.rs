@@ -0,0 +1,441 @@
+use crate::{server::spawn_router, tenant::host_patterns, ServerHandle};
+use anyhow::{Context, Result};
+use axum::{extract::State, http::Uri, response::Redirect, serve::Listener, Router};
+use axum_extra::extract::Host;
+use dashmap::DashMap;
+use rustls::{
+    crypto::ring::sign::any_supported_type,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use serde::Deserialize;
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+    time::timeout,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// clients not finishing the handshake in time are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// pem encoded certificate chain and private key of a tenant host
+#[derive(Deserialize, Debug, Clone)]
+pub struct CertConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug)]
+struct CertEntry {
+    config: CertConfig,
+    key: Arc<CertifiedKey>,
+    modified: SystemTime,
+}
+
+/// certificates by host, picked by the SNI of the client hello
+#[derive(Debug, Clone, Default)]
+pub struct CertStore {
+    certs: Arc<DashMap<String, CertEntry>>,
+    /// host of the certificate for clients without SNI, e.g. connecting by ip
+    default: Arc<RwLock<Option<String>>>,
+}
+
+/// accepts tcp connections and hands out streams once their tls handshake finished
+pub struct TlsListener {
+    addr: SocketAddr,
+    rx: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    task: JoinHandle<()>,
+}
+
+impl CertStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, host: impl Into<String>, config: CertConfig) -> Result<()> {
+        let host = host.into().to_lowercase();
+        let entry = CertEntry::load(config).with_context(|| format!("certificate of {host}"))?;
+        self.certs.insert(host, entry);
+        Ok(())
+    }
+
+    /// the certificate of `host` is sent to clients without SNI
+    pub fn set_default(&self, host: &str) {
+        *self.default.write().unwrap() = Some(host.to_lowercase());
+    }
+
+    pub fn default_host(&self) -> Option<String> {
+        self.default.read().unwrap().clone()
+    }
+
+    pub fn remove(&self, host: &str) {
+        self.certs.remove(&host.to_lowercase());
+    }
+
+    pub fn hosts(&self) -> Vec<String> {
+        self.certs.iter().map(|e| e.key().clone()).collect()
+    }
+
+    /// reload the certificates changed on disk, a broken file keeps the previous certificate
+    pub fn reload(&self) -> usize {
+        let mut reloaded = 0;
+        for mut entry in self.certs.iter_mut() {
+            let modified = match modified(&entry.config) {
+                Ok(modified) if modified != entry.modified => modified,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("Failed to check certificate of {}: {}", entry.key(), e);
+                    continue;
+                }
+            };
+
+            match CertEntry::load(entry.config.clone()) {
+                Ok(cert) => {
+                    info!("Reloaded certificate of {}", entry.key());
+                    *entry.value_mut() = cert;
+                    reloaded += 1;
+                }
+                Err(e) => {
+                    warn!("Failed to reload certificate of {}: {:#}", entry.key(), e);
+                    entry.modified = modified;
+                }
+            }
+        }
+
+        reloaded
+    }
+
+    /// poll the certificate files until shutdown
+    pub async fn watch(self, interval: Duration, shutdown: CancellationToken) {
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(interval) => {
+                    self.reload();
+                }
+            }
+        }
+    }
+
+    pub fn server_config(&self) -> Arc<rustls::ServerConfig> {
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(self.clone()));
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Arc::new(config)
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let Some(name) = hello.server_name() else {
+            let default = self.default.read().unwrap();
+            return default
+                .as_ref()
+                .and_then(|host| self.certs.get(host).map(|entry| entry.key.clone()));
+        };
+        // same precedence as the tenants, so a wildcard certificate covers its subdomains
+        host_patterns(name)
+            .iter()
+            .find_map(|host| self.certs.get(host).map(|entry| entry.key.clone()))
+    }
+}
+
+impl CertEntry {
+    fn load(config: CertConfig) -> Result<Self> {
+        let modified = modified(&config)?;
+        let certs = CertificateDer::pem_file_iter(&config.cert)
+            .with_context(|| format!("failed to read {}", config.cert.display()))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_file(&config.key)
+            .with_context(|| format!("failed to read {}", config.key.display()))?;
+        let key = CertifiedKey::new(certs, any_supported_type(&key)?);
+        key.keys_match()?;
+
+        Ok(Self {
+            config,
+            key: Arc::new(key),
+            modified,
+        })
+    }
+}
+
+/// the latest modification of the certificate and key files
+fn modified(config: &CertConfig) -> io::Result<SystemTime> {
+    let cert = fs::metadata(&config.cert)?.modified()?;
+    let key = fs::metadata(&config.key)?.modified()?;
+    Ok(cert.max(key))
+}
+
+impl CertConfig {
+    pub(crate) fn resolve(&mut self, base: &Path) {
+        self.cert = base.join(&self.cert);
+        self.key = base.join(&self.key);
+    }
+}
+
+impl TlsListener {
+    /// handshakes run in their own tasks, so a slow client does not block accepting others
+    pub fn new(listener: TcpListener, config: Arc<rustls::ServerConfig>) -> io::Result<Self> {
+        let addr = listener.local_addr()?;
+        let acceptor = TlsAcceptor::from(config);
+        let (tx, rx) = mpsc::channel(64);
+
+        let task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = tokio::select! {
+                    _ = tx.closed() => break,
+                    ret = listener.accept() => match ret {
+                        Ok(conn) => conn,
+                        Err(e) => {
+                            warn!("Failed to accept connection: {}", e);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    },
+                };
+
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send((stream, peer)).await;
+                        }
+                        Ok(Err(e)) => warn!("TLS handshake with {} failed: {}", peer, e),
+                        Err(_) => warn!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+
+        Ok(Self { addr, rx, task })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.rx.recv().await {
+            Some(conn) => conn,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.addr)
+    }
+}
+
+impl Drop for TlsListener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// redirect every plaintext request to the same host and path on the https port
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(redirect).with_state(https_port)
+}
+
+pub async fn spawn_redirect_server(
+    addr: SocketAddr,
+    https_port: u16,
+    drain_timeout: Duration,
+    shutdown: CancellationToken,
+) -> Result<ServerHandle> {
+    let listener = TcpListener::bind(addr).await?;
+    spawn_router(
+        listener,
+        redirect_router(https_port),
+        drain_timeout,
+        shutdown,
+    )
+}
+
+async fn redirect(State(port): State<u16>, Host(host): Host, uri: Uri) -> Redirect {
+    let host = match host.rsplit_once(':') {
+        Some((name, p)) if p.parse::<u16>().is_ok() => name,
+        _ => host.as_str(),
+    };
+    let authority = match port {
+        443 => host.to_string(),
+        port => format!("{host}:{port}"),
+    };
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+    Redirect::permanent(&format!("https://{authority}{path}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DinoServer, ProjectConfig, SwappableAppRouter, TenentRouter};
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+    use tower::ServiceExt;
+
+    const CODE: &str = r#"(function(){
+        return { hello: async (req) => ({ status: 200, headers: {}, body: "hello" }) };
+    })()"#;
+
+    fn cert(host: &str) -> CertConfig {
+        CertConfig {
+            cert: format!("fixtures/certs/{host}.crt").into(),
+            key: format!("fixtures/certs/{host}.key").into(),
+        }
+    }
+
+    fn connector() -> Result<TlsConnector> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter("fixtures/certs/ca.crt")? {
+            roots.add(cert?)?;
+        }
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+
+    async fn get(addr: SocketAddr, host: &'static str) -> Result<String> {
+        get_with(connector()?, addr, host).await
+    }
+
+    async fn get_with(
+        connector: TlsConnector,
+        addr: SocketAddr,
+        host: &'static str,
+    ) -> Result<String> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = connector.connect(host.try_into()?, stream).await?;
+        let req = format!("GET /api/hello/1 HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+        stream.write_all(req.as_bytes()).await?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await?;
+
+        Ok(res)
+    }
+
+    #[tokio::test]
+    async fn tls_server_should_select_cert_by_sni() -> Result<()> {
+        let config: ProjectConfig =
+            serde_yml::from_str(include_str!("../fixtures/config.yml")).unwrap();
+        let router = SwappableAppRouter::try_new(CODE, config)?;
+        let routers = vec![
+            TenentRouter::new("localhost", router.clone()),
+            TenentRouter::new("dino.local", router),
+        ];
+
+        let certs = CertStore::new();
+        certs.insert("localhost", cert("localhost"))?;
+        certs.insert("dino.local", cert("dino.local"))?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let listener = TlsListener::new(listener, certs.server_config())?;
+        let handle = DinoServer::new(routers).spawn_with_listener(listener)?;
+        let addr = handle.local_addr();
+
+        assert!(get(addr, "localhost").await?.starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "dino.local").await?.starts_with("HTTP/1.1 200"));
+
+        certs.remove("dino.local");
+        assert!(get(addr, "dino.local").await.is_err());
+
+        // clients without SNI, like those connecting by ip, get the default certificate
+        let mut config = (**connector()?.config()).clone();
+        config.enable_sni = false;
+        let no_sni = TlsConnector::from(Arc::new(config));
+        assert!(get_with(no_sni.clone(), addr, "localhost").await.is_err());
+        certs.set_default("localhost");
+        let res = get_with(no_sni, addr, "localhost").await?;
+        assert!(res.starts_with("HTTP/1.1 200"));
+
+        handle.shutdown();
+        handle.wait().await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn cert_store_reload_should_keep_valid_cert() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = CertConfig {
+            cert: dir.path().join("localhost.crt"),
+            key: dir.path().join("localhost.key"),
+        };
+        // explicit mtimes, so the changes are seen on filesystems with coarse timestamps
+        let copy = |from: &str, to: &Path, secs: i64| -> Result<()> {
+            fs::copy(from, to)?;
+            filetime::set_file_mtime(to, filetime::FileTime::from_unix_time(secs, 0))?;
+            Ok(())
+        };
+        copy("fixtures/certs/localhost.crt", &config.cert, 1_000)?;
+        copy("fixtures/certs/localhost.key", &config.key, 1_000)?;
+
+        let certs = CertStore::new();
+        certs.insert("LocalHost", config.clone())?;
+        assert_eq!(certs.hosts(), ["localhost"]);
+        assert_eq!(certs.reload(), 0);
+
+        // a key of another host does not match the certificate
+        copy("fixtures/certs/dino.local.key", &config.key, 2_000)?;
+        assert_eq!(certs.reload(), 0);
+
+        copy("fixtures/certs/dino.local.crt", &config.cert, 3_000)?;
+        assert_eq!(certs.reload(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redirect_router_should_work() -> Result<()> {
+        let req = |port: u16| {
+            redirect_router(port).oneshot(
+                Request::builder()
+                    .uri("/api/hello/1?a=b")
+                    .header("host", "dino.local:8080")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let res = req(443).await?;
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            res.headers()["location"],
+            "https://dino.local/api/hello/1?a=b"
+        );
+
+        let res = req(8443).await?;
+        assert_eq!(
+            res.headers()["location"],
+            "https://dino.local:8443/api/hello/1?a=b"
+        );
+
+        Ok(())
+    }
+}
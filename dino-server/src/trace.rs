@@ -0,0 +1,254 @@
+use crate::OtelConfig;
+use axum::http::HeaderMap;
+use tracing::{warn, Span};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt as _, util::SubscriberInitExt as _, EnvFilter, Layer as _,
+};
+
+#[cfg(feature = "otel")]
+use opentelemetry::{
+    propagation::{Extractor, TextMapPropagator},
+    trace::TracerProvider as _,
+};
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+#[cfg(feature = "otel")]
+use std::collections::HashMap;
+#[cfg(feature = "otel")]
+use tracing::Subscriber;
+#[cfg(feature = "otel")]
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+#[cfg(feature = "otel")]
+use tracing_subscriber::registry::LookupSpan;
+
+/// w3c trace context of the caller, e.g. `00-{trace id}-{parent id}-01`
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// flushes the pending spans when dropped, keep it until the process exits
+#[must_use]
+pub struct TracingGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<SdkTracerProvider>,
+}
+
+/// log to stdout filtered by `RUST_LOG`, and export spans if otel is configured
+pub fn init_tracing(otel: Option<&OtelConfig>) -> anyhow::Result<TracingGuard> {
+    let fmt = fmt::layer().with_filter(EnvFilter::from_default_env());
+
+    #[cfg(feature = "otel")]
+    {
+        let (layer, provider) = match otel {
+            Some(config) => {
+                let (layer, provider) = otel_layer(config)?;
+                (Some(layer), Some(provider))
+            }
+            None => (None, None),
+        };
+        tracing_subscriber::registry().with(layer).with(fmt).init();
+        Ok(TracingGuard { provider })
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        tracing_subscriber::registry().with(fmt).init();
+        if otel.is_some() {
+            warn!("dino-server is built without the otel feature, traces are not exported");
+        }
+        Ok(TracingGuard {})
+    }
+}
+
+#[cfg(feature = "otel")]
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                warn!("Export pending spans failed: {}", e);
+            }
+        }
+    }
+}
+
+/// a layer exporting spans to the otlp/http collector in batches
+#[cfg(feature = "otel")]
+pub fn otel_layer<S>(
+    config: &OtelConfig,
+) -> anyhow::Result<(
+    OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>,
+    SdkTracerProvider,
+)>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let endpoint = format!("{}/v1/traces", config.endpoint.trim_end_matches('/'));
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer("dino-server");
+
+    Ok((tracing_opentelemetry::layer().with_tracer(tracer), provider))
+}
+
+/// continue the trace of the caller, if it sent a `traceparent`
+pub(crate) fn set_parent(span: &Span, headers: &HeaderMap) {
+    #[cfg(feature = "otel")]
+    {
+        let cx = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+        let _ = span.set_parent(cx);
+    }
+
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, headers);
+}
+
+/// the `traceparent` a handler should send with its own requests
+///
+/// it points to the current span if spans are exported, otherwise the one of the caller is kept
+pub fn traceparent(headers: &HeaderMap) -> Option<String> {
+    #[cfg(feature = "otel")]
+    {
+        let mut carrier = HashMap::new();
+        TraceContextPropagator::new().inject_context(&Span::current().context(), &mut carrier);
+        if let Some(traceparent) = carrier.remove(TRACEPARENT_HEADER) {
+            return Some(traceparent);
+        }
+    }
+
+    let traceparent = headers.get(TRACEPARENT_HEADER)?.to_str().ok()?;
+    is_valid_traceparent(traceparent).then(|| traceparent.to_string())
+}
+
+fn is_valid_traceparent(traceparent: &str) -> bool {
+    let parts = traceparent.split('-').collect::<Vec<_>>();
+    let [version, trace_id, parent_id, flags] = parts[..] else {
+        return false;
+    };
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let is_zero = |s: &str| s.bytes().all(|b| b == b'0');
+
+    is_hex(version, 2)
+        && version != "ff"
+        && is_hex(trace_id, 32)
+        && !is_zero(trace_id)
+        && is_hex(parent_id, 16)
+        && !is_zero(parent_id)
+        && is_hex(flags, 2)
+}
+
+#[cfg(feature = "otel")]
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+#[cfg(feature = "otel")]
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    #[test]
+    fn traceparent_should_work() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(traceparent(&headers), None);
+
+        headers.insert(TRACEPARENT_HEADER, TRACEPARENT.parse().unwrap());
+        assert_eq!(traceparent(&headers).as_deref(), Some(TRACEPARENT));
+
+        for invalid in [
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        ] {
+            headers.insert(TRACEPARENT_HEADER, invalid.parse().unwrap());
+            assert_eq!(traceparent(&headers), None, "{invalid}");
+        }
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn otel_export_should_work() -> anyhow::Result<()> {
+        use crate::RequestIdLayer;
+        use axum::{
+            body::{Body, Bytes},
+            extract::Request,
+            routing::{get, post},
+            Router,
+        };
+        use http_body_util::BodyExt;
+        use opentelemetry::trace::TraceId;
+        use tokio::{net::TcpListener, sync::mpsc};
+        use tower::ServiceExt;
+
+        // a stand-in collector which keeps the exported bodies
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let collector = Router::new().route(
+            "/v1/traces",
+            post(move |body: Bytes| {
+                let tx = tx.clone();
+                async move {
+                    let _ = tx.send(body);
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, collector).await });
+
+        let config = OtelConfig {
+            endpoint,
+            service_name: "dino-test".to_string(),
+        };
+        let (layer, provider) = otel_layer(&config)?;
+        let subscriber = tracing_subscriber::registry().with(layer);
+        let guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/",
+                get(|headers: HeaderMap| async move { traceparent(&headers).unwrap_or_default() }),
+            )
+            .layer(RequestIdLayer);
+        let req = Request::builder()
+            .uri("/")
+            .header(TRACEPARENT_HEADER, TRACEPARENT)
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        let body = res.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8(body.to_vec())?;
+        drop(guard);
+
+        // the handler continues the trace of the caller, with the request span as the parent
+        assert!(body.starts_with("00-0af7651916cd43dd8448eb211c80319c-"));
+        assert!(!body.contains("b7ad6b7169203331"));
+
+        tokio::task::spawn_blocking(move || provider.shutdown()).await??;
+        let exported = rx.recv().await.unwrap();
+        let trace_id = TraceId::from_hex("0af7651916cd43dd8448eb211c80319c")?.to_bytes();
+        assert!(exported.windows(16).any(|w| w == trace_id));
+        assert!(exported.windows(9).any(|w| w == b"dino-test"));
+
+        Ok(())
+    }
+}
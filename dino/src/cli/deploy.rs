@@ -0,0 +1,109 @@
+use crate::{build_project, CmdExecutor};
+use anyhow::{bail, Result};
+use clap::Parser;
+use dino_server::{Deployment, ProjectConfig, TenantInfo};
+use std::{env, fs, path::Path};
+
+#[derive(Parser, Debug)]
+pub struct DeployOpts {
+    // url of the dino-server admin api, e.g. http://localhost:8081
+    #[arg(short, long)]
+    pub server: String,
+    // tenant host to deploy to
+    #[arg(long)]
+    pub host: String,
+    // token of the admin api
+    #[arg(short, long, env = "DINO_DEPLOY_TOKEN")]
+    pub token: String,
+}
+
+impl CmdExecutor for DeployOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let cur = env::current_dir()?.display().to_string();
+        let filename = build_project(&cur)?;
+
+        let deployment = Deployment {
+            version: Path::new(&filename)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string()),
+            code: fs::read_to_string(&filename)?,
+            config: fs::read_to_string(filename.replace(".mjs", ".yml"))?,
+        };
+        // only the bundle and the config are uploaded, the server would not find the files
+        let config: ProjectConfig = serde_yml::from_str(&deployment.config)?;
+        if !config.statics.is_empty() {
+            bail!("static files are not uploaded, remove `static` from config.yml to deploy");
+        }
+
+        let info = deploy(&self.server, &self.host, &self.token, &deployment).await?;
+        println!("Deploy success: {} is running {}", info.host, info.active);
+        Ok(())
+    }
+}
+
+/// upload the bundle and config to the admin api of dino-server
+async fn deploy(
+    server: &str,
+    host: &str,
+    token: &str,
+    deployment: &Deployment,
+) -> Result<TenantInfo> {
+    // a mounted tenant like `localhost/svc` stays a single path segment
+    let host = host.replace('/', "%2F");
+    let url = format!("{}/tenants/{host}", server.trim_end_matches('/'));
+    let res = reqwest::Client::new()
+        .put(url)
+        .bearer_auth(token)
+        .json(deployment)
+        .send()
+        .await?;
+
+    let status = res.status();
+    if !status.is_success() {
+        bail!("deploy failed with {status}: {}", res.text().await?);
+    }
+
+    Ok(res.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dino_server::{admin_router, AppState, DEFAULT_ADMIN_MAX_BODY_SIZE};
+    use tokio::net::TcpListener;
+
+    const TOKEN: &str = "secret";
+    const CODE: &str = "(function(){ return { hello: async (req) => ({ status: 200 }) }; })()";
+
+    async fn start_admin() -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let app = admin_router(
+            AppState::new(Default::default()),
+            TOKEN,
+            DEFAULT_ADMIN_MAX_BODY_SIZE,
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Ok(format!("http://{addr}"))
+    }
+
+    #[tokio::test]
+    async fn deploy_should_work() -> Result<()> {
+        let server = start_admin().await?;
+        let deployment = Deployment {
+            version: Some("af1349b9f5f9".to_string()),
+            code: CODE.to_string(),
+            config: fs::read_to_string("fixtures/prj/config.yml")?,
+        };
+
+        let info = deploy(&server, "localhost", TOKEN, &deployment).await?;
+        assert_eq!(info.host, "localhost");
+        assert_eq!(info.active, "af1349b9f5f9");
+
+        let ret = deploy(&server, "localhost", "wrong", &deployment).await;
+        assert!(ret.is_err());
+
+        Ok(())
+    }
+}
@@ -56,5 +56,9 @@ fn init_project(project_name: &str, path: &Path) -> Result<()> {
     // init .gitignore file
     fs::write(path.join(".gitignore"), GitIgnoreFile {}.render()?)?;
 
+    // init public dir for static files
+    fs::create_dir_all(path.join("public"))?;
+    fs::write(path.join("public/.gitkeep"), "")?;
+
     Ok(())
 }
@@ -1,8 +1,10 @@
 mod build;
+mod deploy;
 mod init;
 mod run;
 
 pub use build::BuildOpts;
+pub use deploy::DeployOpts;
 pub use init::InitOpts;
 pub use run::RunOpts;
 
@@ -25,4 +27,9 @@ pub enum SubCommand {
     Build(BuildOpts),
     #[command(name = "run", about = "Run the project")]
     Run(RunOpts),
+    #[command(
+        name = "deploy",
+        about = "Build and deploy the project to a dino-server"
+    )]
+    Deploy(DeployOpts),
 }
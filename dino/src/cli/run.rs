@@ -1,12 +1,21 @@
 use crate::{build_project, CmdExecutor};
 use clap::Parser;
-use dino_server::{start_server, ProjectConfig, SwappableAppRouter, TenentRouter};
+use dino_server::{
+    metrics, spawn_metrics_server, spawn_server, AppState, ProjectConfig, SwappableAppRouter,
+    TenentRouter,
+};
 use notify::RecursiveMode;
 use notify_debouncer_full::new_debouncer;
-use std::{fs, path::Path, time::Duration};
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
 use tokio::sync::mpsc::channel;
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
-use tracing::info;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 use tracing_subscriber::{
     filter::LevelFilter, fmt::Layer, layer::SubscriberExt as _, util::SubscriberInitExt as _,
     Layer as _,
@@ -19,6 +28,12 @@ pub struct RunOpts {
     // prot to listen
     #[arg(short, long, default_value = "3000")]
     pub port: u16,
+    // seconds for in-flight requests to finish on shutdown
+    #[arg(long, default_value = "30")]
+    pub drain_timeout: u64,
+    // port to serve prometheus metrics on, disabled if not set
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
 }
 
 impl CmdExecutor for RunOpts {
@@ -27,29 +42,48 @@ impl CmdExecutor for RunOpts {
         tracing_subscriber::registry().with(layer).init();
 
         // let cur = env::current_dir()?.display().to_string();
-        let (code, config) = get_code_and_config()?;
+        let (version, code, config) = get_code_and_config()?;
 
-        let router = SwappableAppRouter::try_new(&code, config.routes)?;
+        let router = SwappableAppRouter::try_new_with_version(version, code, config)?;
         let tenent = TenentRouter::new("localhost", router.clone());
 
-        tokio::spawn(watch_project(".", router));
+        let addr = SocketAddr::from(([0, 0, 0, 0], self.port));
+        let state = AppState::from(vec![tenent]);
+        let drain_timeout = Duration::from_secs(self.drain_timeout);
+        let handle = spawn_server(addr, state, drain_timeout).await?;
+        if let Some(port) = self.metrics_port {
+            let addr = SocketAddr::from(([0, 0, 0, 0], port));
+            spawn_metrics_server(addr, drain_timeout, handle.shutdown_token()).await?;
+        }
+
+        let watcher = tokio::spawn(watch_project(".", router, handle.shutdown_token()));
 
-        start_server(self.port, vec![tenent]).await?;
+        handle.wait_for_signal().await?;
+        watcher.await??;
 
         Ok(())
     }
 }
 
-fn get_code_and_config() -> anyhow::Result<(String, ProjectConfig)> {
+/// the version is the build hash, so unchanged projects keep their version
+fn get_code_and_config() -> anyhow::Result<(String, String, ProjectConfig)> {
     let filename = build_project(".")?;
+    let version = Path::new(&filename)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
     let code = fs::read_to_string(&filename)?;
     let config = ProjectConfig::load(filename.replace(".mjs", ".yml"))?;
-    Ok((code, config))
+    Ok((version, code, config))
 }
 
 #[allow(unused_assignments)]
-/// listen to file changes and reload the router
-async fn watch_project(dir: &'static str, router: SwappableAppRouter) -> anyhow::Result<()> {
+/// listen to file changes and reload the router until shutdown
+async fn watch_project(
+    dir: &'static str,
+    router: SwappableAppRouter,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
     let (tx, rx) = channel(1);
 
     let mut debouncer = new_debouncer(MONITOR_FS_INTERVAL, None, move |res| {
@@ -62,22 +96,38 @@ async fn watch_project(dir: &'static str, router: SwappableAppRouter) -> anyhow:
 
     let mut stream = ReceiverStream::new(rx);
 
-    while let Some(ret) = stream.next().await {
+    loop {
+        let ret = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            ret = stream.next() => match ret {
+                Some(ret) => ret,
+                None => break,
+            },
+        };
+
         match ret {
             Ok(events) => {
                 let mut need_swap = false;
 
+                // the static files are part of the build hash
+                let static_dirs = static_dirs(dir);
                 for event in events {
                     // warn!("event: {:?}", event);
-                    need_swap = event.paths.iter().any(|p| is_ts_or_js_or_config_toml(p));
+                    need_swap = event.paths.iter().any(|p| is_project_file(p, &static_dirs));
 
                     if !need_swap {
                         continue;
                     }
 
                     info!("reloading content...");
-                    let (code, config) = get_code_and_config()?;
-                    router.swap(code, config.routes)?;
+                    // keep serving the previous version if the project is broken
+                    let ret = get_code_and_config()
+                        .and_then(|(version, code, config)| router.deploy(version, code, config));
+                    if let Err(e) = ret {
+                        let name = router.load().config.name.clone();
+                        metrics().record_reload(&name, false);
+                        warn!("reload failed: {:?}", e);
+                    }
                 }
             }
             Err(e) => {
@@ -89,8 +139,52 @@ async fn watch_project(dir: &'static str, router: SwappableAppRouter) -> anyhow:
     Ok(())
 }
 
-/// 判断数组中是否有PathBuf的后缀名是否包含.ts或者.js或者config.toml文件
-fn is_ts_or_js_or_config_toml(path: &Path) -> bool {
+/// the static directories of the project config, as absolute paths
+fn static_dirs(dir: &str) -> Vec<PathBuf> {
+    let Ok(root) = Path::new(dir).canonicalize() else {
+        return vec![];
+    };
+    let config = fs::read_to_string(root.join("config.yml"))
+        .ok()
+        .and_then(|content| serde_yml::from_str::<ProjectConfig>(&content).ok());
+    config
+        .map(|config| config.statics.values().map(|s| root.join(&s.dir)).collect())
+        .unwrap_or_default()
+}
+
+/// 判断路径是否为.ts、.js、config.yml或者静态目录中的文件
+fn is_project_file(path: &Path, static_dirs: &[PathBuf]) -> bool {
     let ext = path.extension().unwrap_or_default();
-    ext == "ts" || ext == "js" || path.file_name().unwrap() == "config.toml"
+    if ext == "ts" || ext == "js" || path.file_name().unwrap_or_default() == "config.yml" {
+        return true;
+    }
+    let path = std::env::current_dir()
+        .map(|cur| cur.join(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+    let path = path
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect::<PathBuf>();
+    static_dirs.iter().any(|dir| path.starts_with(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_project_file_should_work() {
+        let cur = std::env::current_dir().unwrap();
+        let static_dirs = [cur.join("public")];
+
+        assert!(is_project_file(Path::new("./main.ts"), &static_dirs));
+        assert!(is_project_file(Path::new("./config.yml"), &static_dirs));
+        assert!(is_project_file(Path::new("./public/a.css"), &static_dirs));
+        assert!(is_project_file(&cur.join("public/a/b.png"), &static_dirs));
+        assert!(!is_project_file(
+            Path::new("./.build/v1/public/a.css"),
+            &static_dirs
+        ));
+        assert!(!is_project_file(Path::new("./README.md"), &static_dirs));
+    }
 }
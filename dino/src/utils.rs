@@ -1,6 +1,7 @@
 use crate::BUILD_DIR;
 use anyhow::Result;
 use bundler::run_bundle;
+use dino_server::ProjectConfig;
 use glob::{glob, GlobError};
 use std::{
     collections::BTreeSet,
@@ -20,12 +21,28 @@ pub(crate) fn get_files_with_extension(dir: &str, exts: &[&str]) -> Result<BTree
     Ok(all_files)
 }
 
-pub(crate) fn calc_hash_for_project(dir: &str) -> Result<String> {
-    calc_hash_for_files(dir, &["ts", "json"], 16)
+// all files in a directory and its sub directories
+pub(crate) fn get_all_files(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let rule = format!("{}/**/*", dir.display());
+    let files = glob(&rule)?.collect::<Result<BTreeSet<PathBuf>, GlobError>>()?;
+    Ok(files.into_iter().filter(|f| f.is_file()).collect())
 }
 
+/// the static files are part of the build, so changing them changes the hash
+pub(crate) fn calc_hash_for_project(dir: &str, static_dirs: &[&Path]) -> Result<String> {
+    let mut files = get_files_with_extension(dir, &["ts", "json"])?;
+    for static_dir in static_dirs {
+        files.extend(get_all_files(&Path::new(dir).join(static_dir))?);
+    }
+    calc_hash(files, 16)
+}
+
+#[allow(unused)]
 pub(crate) fn calc_hash_for_files(dir: &str, exts: &[&str], len: usize) -> Result<String> {
-    let files = get_files_with_extension(dir, exts)?;
+    calc_hash(get_files_with_extension(dir, exts)?, len)
+}
+
+fn calc_hash(files: BTreeSet<PathBuf>, len: usize) -> Result<String> {
     let mut hasher = blake3::Hasher::new();
     for file in files {
         hasher.update_reader(fs::File::open(file)?)?;
@@ -38,7 +55,16 @@ pub(crate) fn calc_hash_for_files(dir: &str, exts: &[&str], len: usize) -> Resul
 }
 
 pub(crate) fn build_project(dir: &str) -> Result<String> {
-    let hash = calc_hash_for_project(dir)?;
+    let config: ProjectConfig =
+        serde_yml::from_str(&fs::read_to_string(Path::new(dir).join("config.yml"))?)?;
+    // checked before anything is copied into the build directory
+    config.check_static_dirs()?;
+    let static_dirs = config
+        .statics
+        .values()
+        .map(|s| s.dir.as_path())
+        .collect::<Vec<_>>();
+    let hash = calc_hash_for_project(dir, &static_dirs)?;
 
     fs::create_dir_all(BUILD_DIR)?;
 
@@ -63,9 +89,31 @@ pub(crate) fn build_project(dir: &str) -> Result<String> {
 
     io::copy(&mut src, &mut dst)?;
 
+    // the server looks for the static files in the directory named after the bundle
+    for static_dir in static_dirs {
+        let src = Path::new(dir).join(static_dir);
+        if src.is_dir() {
+            copy_dir(&src, &Path::new(BUILD_DIR).join(&hash).join(static_dir))?;
+        }
+    }
+
     Ok(filename)
 }
 
+fn copy_dir(src: &Path, dst: &Path) -> Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let dst = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dst)?;
+        } else {
+            fs::copy(entry.path(), dst)?;
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,6 +135,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn build_project_should_reject_static_dir_outside_project() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = "name: a\nstatic:\n  /assets:\n    dir: ../x\nroutes: {}\n";
+        fs::write(dir.path().join("config.yml"), config)?;
+
+        assert!(build_project(&dir.path().display().to_string()).is_err());
+        assert!(!dir.path().join("../x").exists());
+
+        Ok(())
+    }
+
     #[test]
     fn calc_hash_for_files_should_work() -> Result<()> {
         let hash = calc_hash_for_files("fixtures/prj", &["ts", "js", "json"], 12)?;